@@ -0,0 +1,291 @@
+use crate::flip_fluid::components::{Advection, FlipFluid};
+use crate::flip_fluid::obstacle::Obstacle;
+use bevy::prelude::*;
+use std::f32::consts::TAU;
+
+// Launch velocity per unit of flick velocity, and spin per unit of sideways flick
+const THROW_SCALE: f32 = 0.5;
+const MAX_THROW_SPEED: f32 = 500.;
+const SPIN_SCALE: f32 = 0.012;
+const MAX_SPIN: f32 = 12.;
+
+// The bottle and the water inside count as resting below these speeds
+const REST_SPEED: f32 = 5.;
+const REST_ANGULAR_SPEED: f32 = 0.2;
+const REST_WATER_SPEED: f32 = 10.;
+// How long everything has to rest before the landing is judged
+const REST_TIME: f32 = 0.5;
+const UPRIGHT_ANGLE: f32 = 0.2;
+
+// The body narrows in steps from the shoulder height up to the neck, in tank space
+const SHOULDER_HEIGHT: f32 = 28.;
+const SHOULDER_STEPS: usize = 3;
+const SHOULDER_STEP_HEIGHT: f32 = 3.;
+const NECK_WIDTH: f32 = 8.;
+// Rows of water particles, about a third of the body
+const FILL_ROWS: usize = 24;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FlipPhase {
+    #[default]
+    Ready,
+    // Mouse button held down, the flick follows the recent pointer velocity
+    Aiming {
+        flick: Vec2,
+    },
+    Flying {
+        turned: f32,
+        landed: bool,
+        resting_time: f32,
+    },
+    Done {
+        upright: bool,
+    },
+}
+
+// Bottle-flip minigame. A flick throws the bottle, it scores when bottle and water come to
+// rest upright after turning over once. Water still sloshing after the bottle touched down
+// can tip it over, so nothing is judged before the water rests too.
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+pub struct BottleFlip {
+    pub phase: FlipPhase,
+    pub score: u32,
+    pub attempts: u32,
+}
+
+// State of the thrown bottle during a frame, speeds in world units
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FlightSample {
+    pub dt: f32,
+    pub angle: f32,
+    pub speed: f32,
+    pub angular_velocity: f32,
+    // Root mean square speed of the water relative to the bottle
+    pub water_speed: f32,
+    pub touched: bool,
+}
+
+impl BottleFlip {
+    pub fn aim(&mut self) {
+        if matches!(self.phase, FlipPhase::Ready | FlipPhase::Done { .. }) {
+            self.phase = FlipPhase::Aiming { flick: Vec2::ZERO };
+        }
+    }
+
+    // Smooths the pointer velocity over the last few frames
+    pub fn track_flick(&mut self, pointer_velocity: Vec2) {
+        if let FlipPhase::Aiming { flick } = &mut self.phase {
+            *flick = flick.lerp(pointer_velocity, 0.5);
+        }
+    }
+
+    // Linear and angular velocity to launch the bottle with, if it was being aimed
+    pub fn throw(&mut self) -> Option<(Vec2, f32)> {
+        let FlipPhase::Aiming { flick } = self.phase else {
+            return None;
+        };
+
+        self.attempts += 1;
+        self.phase = FlipPhase::Flying {
+            turned: 0.,
+            landed: false,
+            resting_time: 0.,
+        };
+        Some(launch_velocity(flick))
+    }
+
+    // Follows the flight, returns whether the bottle landed upright once it has settled
+    pub fn update(&mut self, sample: FlightSample) -> Option<bool> {
+        let FlipPhase::Flying {
+            turned,
+            landed,
+            resting_time,
+        } = &mut self.phase
+        else {
+            return None;
+        };
+
+        *turned += sample.angular_velocity * sample.dt;
+        *landed |= sample.touched;
+        let resting = sample.speed < REST_SPEED
+            && sample.angular_velocity.abs() < REST_ANGULAR_SPEED
+            && sample.water_speed < REST_WATER_SPEED;
+        *resting_time = if *landed && resting {
+            *resting_time + sample.dt
+        } else {
+            0.
+        };
+        if *resting_time < REST_TIME {
+            return None;
+        }
+
+        let upright = sample.angle.abs() < UPRIGHT_ANGLE && turned.abs() > TAU - UPRIGHT_ANGLE;
+        if upright {
+            self.score += 1;
+        }
+        self.phase = FlipPhase::Done { upright };
+        Some(upright)
+    }
+}
+
+// Bottle of the given outer size, filled with water up to about a third. Stepped shoulders
+// narrow it down to the neck, the water sloshing in the body shifts the weight while it tumbles.
+pub fn bottle_fluid(width: f32, height: f32) -> FlipFluid {
+    let density = 1000.;
+    let particle_radius = 0.2;
+    let spacing = 2.;
+    let num_x = ((width - 2. * spacing) / (2. * particle_radius)) as usize;
+    let mut fluid = FlipFluid::new(
+        density,
+        width,
+        height,
+        spacing,
+        particle_radius,
+        num_x * FILL_ROWS,
+    )
+    .with_solid_border()
+    .with_bottle_neck()
+    .with_particles(num_x, FILL_ROWS)
+    .with_advection(Advection::RungeKutta3);
+
+    let neck_x = (width - NECK_WIDTH) * 0.5;
+    let step_width = neck_x / SHOULDER_STEPS as f32;
+    for k in 0..SHOULDER_STEPS {
+        let bottom = SHOULDER_HEIGHT + k as f32 * SHOULDER_STEP_HEIGHT;
+        let x = Vec2::new(k as f32, (k + 1) as f32) * step_width;
+        for (left, right) in [(x.x, x.y), (width - x.y, width - x.x)] {
+            fluid = fluid.with_obstacle(Obstacle {
+                min: Vec2::new(left, bottom),
+                max: Vec2::new(right, height),
+                wetting: None,
+            });
+        }
+    }
+
+    fluid
+}
+
+// Flicking to the right spins the bottle clockwise, like a throw from the right hand
+pub fn launch_velocity(flick: Vec2) -> (Vec2, f32) {
+    let velocity = (flick * THROW_SCALE).clamp_length_max(MAX_THROW_SPEED);
+    let spin = (-flick.x * SPIN_SCALE).clamp(-MAX_SPIN, MAX_SPIN);
+    (velocity, spin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn fly(flip: &mut BottleFlip, samples: impl Iterator<Item = FlightSample>) -> Option<bool> {
+        samples.filter_map(|sample| flip.update(sample)).next()
+    }
+
+    fn spin(angular_velocity: f32, frames: usize) -> impl Iterator<Item = FlightSample> {
+        (0..frames).map(move |_| FlightSample {
+            dt: 0.01,
+            speed: 100.,
+            angular_velocity,
+            ..default()
+        })
+    }
+
+    fn rest(angle: f32, water_speed: f32, frames: usize) -> impl Iterator<Item = FlightSample> {
+        (0..frames).map(move |_| FlightSample {
+            dt: 0.01,
+            angle,
+            water_speed,
+            touched: true,
+            ..default()
+        })
+    }
+
+    fn thrown() -> BottleFlip {
+        let mut flip = BottleFlip::default();
+        flip.aim();
+        flip.track_flick(Vec2::new(-300., 600.));
+        flip.throw().unwrap();
+        flip
+    }
+
+    #[test]
+    fn upright_landing_after_a_full_turn_scores() {
+        let mut flip = thrown();
+
+        let result = fly(&mut flip, spin(TAU, 100).chain(rest(0.05, 0., 100)));
+
+        assert_eq!(result, Some(true));
+        assert_eq!(flip.score, 1);
+        assert_eq!(flip.attempts, 1);
+    }
+
+    #[test]
+    fn landing_without_turning_over_does_not_score() {
+        let mut flip = thrown();
+
+        let result = fly(&mut flip, spin(0.5, 100).chain(rest(0., 0., 100)));
+
+        assert_eq!(result, Some(false));
+        assert_eq!(flip.score, 0);
+    }
+
+    #[test]
+    fn landing_waits_for_the_water_to_settle() {
+        let mut flip = thrown();
+
+        assert_eq!(
+            fly(&mut flip, spin(TAU, 100).chain(rest(0., 50., 100))),
+            None
+        );
+        // Tipped over by the sloshing
+        assert_eq!(fly(&mut flip, rest(FRAC_PI_2, 0., 100)), Some(false));
+    }
+
+    #[test]
+    fn upside_down_water_only_runs_into_the_neck() {
+        let (width, height) = (30., 50.);
+        let mut fluid = bottle_fluid(width, height);
+        let num_particles = fluid.num_particles();
+
+        for _ in 0..120 {
+            fluid.simulate(
+                1. / 60.,
+                0.,
+                400.,
+                0.,
+                0.,
+                0.,
+                0.,
+                0.9,
+                50,
+                2,
+                1.9,
+                true,
+                true,
+            );
+        }
+
+        assert_eq!(fluid.num_particles(), num_particles);
+        let neck = (width - NECK_WIDTH) * 0.5..=(width + NECK_WIDTH) * 0.5;
+        let mut in_neck = 0;
+        for i in 0..num_particles {
+            let pos = fluid.position(i);
+            assert!(pos.is_finite());
+            if pos.y > SHOULDER_HEIGHT + SHOULDER_STEPS as f32 * SHOULDER_STEP_HEIGHT {
+                assert!(neck.contains(&pos.x), "{pos} outside the neck");
+                in_neck += 1;
+            }
+        }
+        // The rest of the water is held back by the shoulders
+        assert!(in_neck > 0 && in_neck < num_particles / 2);
+    }
+
+    #[test]
+    fn flick_to_the_right_spins_clockwise() {
+        let (velocity, spin) = launch_velocity(Vec2::new(200., 800.));
+
+        assert_eq!(velocity, Vec2::new(100., 400.));
+        assert!(spin < 0.);
+        assert!(launch_velocity(Vec2::splat(1e5)).0.length() <= MAX_THROW_SPEED + 0.001);
+    }
+}
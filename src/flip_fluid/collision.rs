@@ -0,0 +1,219 @@
+use bevy::prelude::*;
+
+// Slower impacts don't bounce, so that resting bodies settle instead of jittering
+const RESTING_SPEED: f32 = 20.;
+
+// Box shaped body that bounces off static colliders, centered on the entity translation
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Collider {
+    pub half_size: Vec2,
+    pub restitution: f32,
+    pub friction: f32,
+}
+
+// Box in the world that does not move, like a floor or a wall. Its pose is the entity's.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct StaticCollider {
+    pub half_size: Vec2,
+    pub restitution: f32,
+    pub friction: f32,
+}
+
+// Velocity change a collision gave a body during the current frame, in world space
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Impact {
+    pub linear_velocity: Vec2,
+    pub angular_velocity: f32,
+}
+
+impl Impact {
+    pub fn is_empty(&self) -> bool {
+        self.linear_velocity == Vec2::ZERO && self.angular_velocity == 0.
+    }
+}
+
+// Normal points from the static box towards the body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contact {
+    pub point: Vec2,
+    pub normal: Vec2,
+    pub depth: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrientedBox {
+    pub center: Vec2,
+    pub angle: f32,
+    pub half_size: Vec2,
+}
+
+impl OrientedBox {
+    pub fn corners(&self) -> [Vec2; 4] {
+        let rotation = Vec2::from_angle(self.angle);
+        [
+            Vec2::new(-1., -1.),
+            Vec2::new(1., -1.),
+            Vec2::new(1., 1.),
+            Vec2::new(-1., 1.),
+        ]
+        .map(|corner| self.center + rotation.rotate(corner * self.half_size))
+    }
+
+    // Penetration and outward normal of the nearest side, if the point is inside
+    fn penetration(&self, point: Vec2) -> Option<(f32, Vec2)> {
+        let local = Vec2::from_angle(-self.angle).rotate(point - self.center);
+        let depth = self.half_size - local.abs();
+        if depth.cmple(Vec2::ZERO).any() {
+            return None;
+        }
+
+        let normal = if depth.x < depth.y {
+            Vec2::X * local.x.signum()
+        } else {
+            Vec2::Y * local.y.signum()
+        };
+        Some((
+            depth.min_element(),
+            Vec2::from_angle(self.angle).rotate(normal),
+        ))
+    }
+}
+
+// Corners of either box that are inside the other one
+pub fn box_contacts(body: &OrientedBox, solid: &OrientedBox) -> Vec<Contact> {
+    let body_corners = body.corners().into_iter().filter_map(|point| {
+        solid.penetration(point).map(|(depth, normal)| Contact {
+            point,
+            normal,
+            depth,
+        })
+    });
+    let solid_corners = solid.corners().into_iter().filter_map(|point| {
+        body.penetration(point).map(|(depth, normal)| Contact {
+            point,
+            normal: -normal,
+            depth,
+        })
+    });
+
+    body_corners.chain(solid_corners).collect()
+}
+
+// Single contact in the middle of the touching corners, along the deepest normal. A box
+// landing flat is pushed straight back instead of being rocked corner by corner.
+pub fn merge_contacts(contacts: &[Contact]) -> Option<Contact> {
+    let deepest = contacts.iter().max_by(|a, b| a.depth.total_cmp(&b.depth))?;
+    let point = contacts.iter().map(|contact| contact.point).sum::<Vec2>() / contacts.len() as f32;
+
+    Some(Contact { point, ..*deepest })
+}
+
+// Impulse on a body hitting a static solid at a point `arm` away from its center. Nothing
+// happens while the point is already separating.
+pub fn contact_impulse(
+    contact: &Contact,
+    arm: Vec2,
+    velocity: Vec2,
+    mass: f32,
+    moment_of_inertia: f32,
+    restitution: f32,
+    friction: f32,
+) -> Vec2 {
+    let normal_speed = velocity.dot(contact.normal);
+    if normal_speed >= 0. {
+        return Vec2::ZERO;
+    }
+
+    let restitution = if normal_speed > -RESTING_SPEED {
+        0.
+    } else {
+        restitution
+    };
+    let inverse_mass =
+        |direction: Vec2| 1. / mass + arm.perp_dot(direction).powi(2) / moment_of_inertia;
+    let normal_impulse = -(1. + restitution) * normal_speed / inverse_mass(contact.normal);
+
+    // Coulomb friction, at most stops the sliding
+    let tangent = contact.normal.perp();
+    let tangent_speed = velocity.dot(tangent);
+    let tangent_impulse = (-tangent_speed / inverse_mass(tangent))
+        .clamp(-friction * normal_impulse, friction * normal_impulse);
+
+    contact.normal * normal_impulse + tangent * tangent_impulse
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floor() -> OrientedBox {
+        OrientedBox {
+            center: Vec2::new(0., -10.),
+            angle: 0.,
+            half_size: Vec2::new(100., 10.),
+        }
+    }
+
+    #[test]
+    fn tilted_box_touches_the_floor_with_one_corner() {
+        let body = OrientedBox {
+            center: Vec2::new(0., 4.),
+            angle: 0.5,
+            half_size: Vec2::new(3., 3.),
+        };
+
+        let contacts = box_contacts(&body, &floor());
+
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].normal, Vec2::Y);
+        assert!((contacts[0].depth + contacts[0].point.y).abs() < 0.0001);
+    }
+
+    #[test]
+    fn flat_box_touches_the_floor_in_the_middle() {
+        let body = OrientedBox {
+            center: Vec2::new(5., 2.9),
+            angle: 0.,
+            half_size: Vec2::new(3., 3.),
+        };
+
+        let contact = merge_contacts(&box_contacts(&body, &floor())).unwrap();
+
+        assert!(contact.point.distance(Vec2::new(5., -0.1)) < 0.0001);
+        assert_eq!(contact.normal, Vec2::Y);
+        assert_eq!(merge_contacts(&[]), None);
+    }
+
+    #[test]
+    fn dropped_box_bounces_back() {
+        let contact = Contact {
+            point: Vec2::ZERO,
+            normal: Vec2::Y,
+            depth: 0.1,
+        };
+        let velocity = Vec2::new(0., -30.);
+
+        let impulse = contact_impulse(&contact, Vec2::new(0., -3.), velocity, 2., 1., 0.5, 0.5);
+
+        assert!((velocity + impulse / 2.).distance(Vec2::new(0., 15.)) < 0.0001);
+        assert_eq!(
+            contact_impulse(&contact, Vec2::ZERO, -velocity, 2., 1., 0.5, 0.5),
+            Vec2::ZERO
+        );
+    }
+
+    #[test]
+    fn friction_stops_sliding() {
+        let contact = Contact {
+            point: Vec2::ZERO,
+            normal: Vec2::Y,
+            depth: 0.1,
+        };
+
+        // Enough friction to stop the sliding, then too little
+        let impulse = contact_impulse(&contact, Vec2::ZERO, Vec2::new(1., -10.), 1., 1., 0., 0.5);
+        assert_eq!(impulse, Vec2::new(-1., 10.));
+        let impulse = contact_impulse(&contact, Vec2::ZERO, Vec2::new(8., -10.), 1., 1., 0., 0.5);
+        assert_eq!(impulse, Vec2::new(-5., 10.));
+    }
+}
@@ -1,7 +1,15 @@
+use crate::flip_fluid::gas::{Gas, GasSource};
+use crate::flip_fluid::loads::{Load, LoadAccumulator, Solid};
+use crate::flip_fluid::obstacle::Obstacle;
+use crate::flip_fluid::porous::Sponge;
+use crate::flip_fluid::rheology::Rheology;
+use crate::flip_fluid::sediment::Sediment;
+use crate::flip_fluid::wetting::Wetting;
+use crate::flip_fluid::world_frame::MovingTank;
+use crate::utils::mechanics::MassProperties;
 use bevy::color::palettes::basic::{RED, YELLOW};
-use bevy::math::Affine3A;
 use bevy::prelude::*;
-use std::f32::EPSILON;
+use std::f32::consts::{PI, TAU};
 use std::ops::Neg;
 
 #[derive(Component)]
@@ -10,29 +18,276 @@ pub struct LiquidParticle;
 #[derive(Component)]
 pub struct Tank;
 
+// Fluid simulated on a grid fixed in world space, following the motion of the Tank
 #[derive(Component)]
-pub struct LinearVelocity(pub Vec2);
+pub struct WorldFrame;
 
 #[derive(Component)]
-pub struct PrevLinearVelocity(pub Vec2);
+pub struct LinearVelocity(pub Vec2);
 
 #[derive(Component)]
 pub struct AngularVelocity(pub f32);
 
+// Dynamic tank, moved by forces instead of setting its velocities directly
 #[derive(Component)]
-pub struct PrevAngularVelocity(pub f32);
+pub struct RigidBody {
+    pub mass: f32,
+    pub moment_of_inertia: f32,
+}
+
+impl RigidBody {
+    pub fn rectangle(mass: f32, size: Vec2) -> Self {
+        Self {
+            mass,
+            moment_of_inertia: mass * size.length_squared() / 12.,
+        }
+    }
+}
+
+// Applied at the center of mass in world space, e.g. by whatever holds the tank
+#[derive(Component, Default)]
+pub struct ExternalForce {
+    pub force: Vec2,
+    pub torque: f32,
+}
+
+// Force and torque about the tank center the fluid exerted on the tank walls and obstacles
+// during the last step, in world space. The part that accelerates the liquid along with the
+// tank is left out and added to the body as mass and moment of inertia instead, otherwise the
+// coupling lags a step behind and blows up for liquid heavier than the tank.
+#[derive(Component, Default)]
+pub struct FluidReaction {
+    pub force: Vec2,
+    pub torque: f32,
+    pub mass: f32,
+    pub moment_of_inertia: f32,
+}
+
+// Loads the liquid put on each solid during the last step, in world space. Torques are about
+// the entity translation.
+#[derive(Component, Default)]
+pub struct SolidLoads(pub Vec<(Solid, Load)>);
+
+// Constrains how a rigid body moves under the applied forces. Without one it moves freely.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub enum Mount {
+    // Pinned at a world point, only turns around it within the angle limits
+    Hinge {
+        pivot: Vec2,
+        min_angle: f32,
+        max_angle: f32,
+    },
+    // Hangs from a pivot on a rigid massless arm fixed to the tank, with friction in the pivot
+    Pendulum {
+        pivot: Vec2,
+        friction: f32,
+    },
+    // Zero length spring from an anchor to the center of mass, turning freely
+    Spring {
+        anchor: Vec2,
+        stiffness: f32,
+        damping: f32,
+    },
+    // Slides along a line through origin between min and max without turning, like a cart
+    Rail {
+        origin: Vec2,
+        direction: Vec2,
+        min: f32,
+        max: f32,
+    },
+}
+
+impl Mount {
+    // Point the body turns around, if it is pinned
+    pub fn pivot(&self) -> Option<Vec2> {
+        match self {
+            Mount::Hinge { pivot, .. } | Mount::Pendulum { pivot, .. } => Some(*pivot),
+            _ => None,
+        }
+    }
+}
+
+// Pose and velocity of a rigid body at the start of a step. Mass and moment of inertia
+// include the liquid it carries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyState {
+    pub mass: f32,
+    pub moment_of_inertia: f32,
+    pub center: Vec2,
+    pub angle: f32,
+    pub linear_velocity: Vec2,
+    pub angular_velocity: f32,
+}
+
+// Linear and angular velocity of a body after a step under force and torque about its center,
+// as far as the mount lets it move
+pub fn integrate_rigid_body(
+    body: BodyState,
+    mount: Option<Mount>,
+    mut force: Vec2,
+    mut torque: f32,
+    dt: f32,
+) -> (Vec2, f32) {
+    let BodyState {
+        mass,
+        moment_of_inertia,
+        center,
+        angle,
+        linear_velocity,
+        angular_velocity,
+    } = body;
+
+    match mount {
+        None => {}
+        Some(Mount::Spring {
+            anchor,
+            stiffness,
+            damping,
+        }) => {
+            force += stiffness * (anchor - center) - damping * linear_velocity;
+        }
+        Some(Mount::Rail {
+            origin,
+            direction,
+            min,
+            max,
+        }) => {
+            let direction = direction.normalize();
+            let position = (center - origin).dot(direction);
+            let mut speed = linear_velocity.dot(direction) + dt * force.dot(direction) / mass;
+            if (position <= min && speed < 0.) || (position >= max && speed > 0.) {
+                speed = 0.;
+            }
+
+            return (direction * speed, 0.);
+        }
+        Some(Mount::Hinge {
+            pivot,
+            min_angle,
+            max_angle,
+        }) => {
+            let arm = center - pivot;
+            let mut omega = angular_velocity
+                + dt * (torque + arm.perp_dot(force))
+                    / (moment_of_inertia + mass * arm.length_squared());
+            // Relative to the middle of the range, the angles wrap around at +-PI
+            let offset = wrap_angle(angle - (min_angle + max_angle) * 0.5);
+            let half_range = (max_angle - min_angle) * 0.5;
+            if (offset <= -half_range && omega < 0.) || (offset >= half_range && omega > 0.) {
+                omega = 0.;
+            }
+
+            return (arm.perp() * omega, omega);
+        }
+        Some(Mount::Pendulum { pivot, friction }) => {
+            let arm = center - pivot;
+            torque -= friction * angular_velocity;
+            let omega = angular_velocity
+                + dt * (torque + arm.perp_dot(force))
+                    / (moment_of_inertia + mass * arm.length_squared());
+
+            return (arm.perp() * omega, omega);
+        }
+    }
+
+    (
+        linear_velocity + dt * force / mass,
+        angular_velocity + dt * torque / moment_of_inertia,
+    )
+}
+
+// Same angle in (-PI, PI]
+fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = angle.rem_euclid(TAU);
+    if wrapped > PI {
+        wrapped - TAU
+    } else {
+        wrapped
+    }
+}
 
+// Holds a rigid body with a spring and damper, linear towards target and angular upright.
+// A released grip leaves the body to gravity.
 #[derive(Component)]
-pub struct PrevGlobalTransform(pub Affine3A);
+pub struct Grip {
+    pub held: bool,
+    pub target: Vec2,
+    pub stiffness: f32,
+    pub damping: f32,
+    pub angular_stiffness: f32,
+    pub angular_damping: f32,
+}
 
 const FLUID_CELL: i32 = 0;
 const AIR_CELL: i32 = 1;
 const SOLID_CELL: i32 = 2;
 
+// Number of face layers around the fluid that receive extrapolated velocities
+const EXTRAPOLATION_LAYERS: usize = 2;
+
+// Upper bound for the conjugate gradient iterations of the viscosity solve, which
+// usually stops earlier once the residual drops below VISCOSITY_TOLERANCE
+const VISCOSITY_ITERS: usize = 200;
+const VISCOSITY_TOLERANCE: f32 = 1e-4;
+// Number of times the strain rate dependent viscosity is updated from the solved velocities
+const RHEOLOGY_ITERS: usize = 3;
+
+// Granular material is dissipative, particles mostly take the grid velocity
+const GRANULAR_FLIP_RATIO: f32 = 0.05;
+
+const AMBIENT_TEMPERATURE: f32 = 20.;
+
+// Rate at which particles next to a heated or cooled wall take its temperature, per second
+const WALL_HEAT_TRANSFER: f32 = 5.;
+
+// Acceleration moving the contact line towards the static contact angle
+const CONTACT_LINE_STIFFNESS: f32 = 100.;
+
+// Sediment volume fraction a particle can carry at most
+const MAX_SEDIMENT_CONCENTRATION: f32 = 0.5;
+
+// Ice melts this far above the freezing point, avoids flickering at the interface
+const MELTING_HYSTERESIS: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Advection {
+    // Particles move with their own velocity
+    #[default]
+    Euler,
+    // Particles move through the projected grid velocity field
+    RungeKutta2,
+    RungeKutta3,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Wall {
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+impl Wall {
+    // In index order
+    pub const ALL: [Wall; 4] = [Wall::Left, Wall::Right, Wall::Bottom, Wall::Top];
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ColorMode {
+    // Particles in sparse regions are highlighted
+    #[default]
+    Density,
+    // Cold particles are blue, hot particles are red
+    Temperature,
+}
+
 #[derive(Component)]
 pub struct FlipFluid {
     density: f32,
 
+    // Width and height of the tank, centered on the entity
+    size: Vec2,
+
     // Number of cols and rows in staggered grid
     f_num_x: usize,
     f_num_y: usize,
@@ -66,6 +321,10 @@ pub struct FlipFluid {
     cell_type: Vec<i32>,
     cell_color: Vec<f32>,
 
+    // Faces holding fluid or extrapolated velocities
+    valid_u: Vec<bool>,
+    valid_v: Vec<bool>,
+
     max_particles: usize,
     particle_pos: Vec<f32>,
     particle_color: Vec<f32>,
@@ -81,6 +340,83 @@ pub struct FlipFluid {
     first_cell_particle: Vec<usize>,
     cell_particle_ids: Vec<usize>,
     num_particles: usize,
+
+    // Isolated particles (splashes) skip the grid transfers and move ballistically
+    particle_ballistic: Vec<bool>,
+    ballistic_min_neighbours: usize,
+    air_drag: f32,
+
+    // Curl of the velocity field at cell centers
+    curl: Vec<f32>,
+    vorticity_confinement: f32,
+
+    advection: Advection,
+
+    // Effective viscosity at cell centers
+    viscosity: Vec<f32>,
+    rheology: Option<Rheology>,
+
+    // Angle of repose for granular material, None for liquids
+    granular: Option<f32>,
+
+    // Buoyancy from the temperature difference to the ambient, Boussinesq approximation
+    particle_temperature: Vec<f32>,
+    thermal_diffusivity: f32,
+    thermal_expansion: f32,
+    // Indexed by Wall, None for insulating walls
+    wall_temperature: [Option<f32>; 4],
+
+    // Indexed by Wall, None for walls the liquid just slides along
+    wall_wetting: [Option<Wetting>; 4],
+
+    // Particles below the freezing point become ice, rigid in the tank frame.
+    // Cells containing ice are solid for the grid solve.
+    particle_frozen: Vec<bool>,
+    freezing_point: Option<f32>,
+    ice_cell: Vec<bool>,
+    ice_wetting: Option<Wetting>,
+
+    // Air is a light second fluid with its own particles, so pockets and bubbles are kept.
+    // Cells with air but no liquid particles take part in the pressure solve with air_density.
+    particle_air: Vec<bool>,
+    cell_air: Vec<bool>,
+    air_density: f32,
+
+    // Porous cells are partly open in s and add Darcy drag, 1 = no porous material.
+    // Absorbed particles are stored in place until the sponges are squeezed.
+    sponges: Vec<Sponge>,
+    cell_porosity: Vec<f32>,
+    cell_permeability: Vec<f32>,
+    particle_absorbed: Vec<bool>,
+    squeeze: f32,
+
+    // Height of the sediment bed per column of cells, cells below it are solid.
+    // Particles carry sediment as a volume fraction of their area.
+    sediment: Option<Sediment>,
+    bed_height: Vec<f32>,
+    bed_cell: Vec<bool>,
+    particle_sediment: Vec<f32>,
+
+    // Smoke or steam in every non-solid cell instead of liquid particles
+    gas: Option<Gas>,
+    gas_sources: Vec<GasSource>,
+    smoke_density: Vec<f32>,
+    cell_temperature: Vec<f32>,
+
+    // World-frame mode, the grid stays in place and the tank moves through it as a solid.
+    // Cells outside the tank interior are solid and their faces move with the walls.
+    moving_tank: Option<MovingTank>,
+    tank_cell: Vec<bool>,
+
+    // Solid rectangles inside the tank
+    obstacles: Vec<Obstacle>,
+    // Impulses on each solid from the pressure and ballistic particles, indexed by Wall for
+    // the walls, summed up into solid_loads at the end of a step
+    wall_impulses: [LoadAccumulator; 4],
+    obstacle_impulses: Vec<LoadAccumulator>,
+    solid_loads: Vec<(Solid, Load)>,
+
+    color_mode: ColorMode,
 }
 
 impl FlipFluid {
@@ -103,6 +439,7 @@ impl FlipFluid {
 
         Self {
             density,
+            size: Vec2::new(width, height),
             f_num_x,
             f_num_y,
             h,
@@ -118,6 +455,8 @@ impl FlipFluid {
             s: vec![1.; f_num_cells], // 1 = fluid (liquid or empty), 0 = solid
             cell_type: vec![i32::default(); f_num_cells],
             cell_color: vec![f32::default(); f_num_cells * 3],
+            valid_u: vec![false; f_num_cells],
+            valid_v: vec![false; f_num_cells],
             max_particles,
 
             particle_pos: vec![f32::default(); max_particles * 2],
@@ -134,9 +473,51 @@ impl FlipFluid {
             first_cell_particle: vec![usize::default(); p_num_cells + 1],
             cell_particle_ids: vec![usize::default(); max_particles],
             num_particles: 0,
+            particle_ballistic: vec![false; max_particles],
+            ballistic_min_neighbours: 0,
+            air_drag: 0.,
+            curl: vec![f32::default(); f_num_cells],
+            vorticity_confinement: 0.,
+            advection: Advection::default(),
+            viscosity: vec![f32::default(); f_num_cells],
+            rheology: None,
+            granular: None,
+            particle_temperature: vec![AMBIENT_TEMPERATURE; max_particles],
+            thermal_diffusivity: 0.,
+            thermal_expansion: 0.,
+            wall_temperature: [None; 4],
+            wall_wetting: [None; 4],
+            particle_frozen: vec![false; max_particles],
+            freezing_point: None,
+            ice_cell: vec![false; f_num_cells],
+            ice_wetting: None,
+            particle_air: vec![false; max_particles],
+            cell_air: vec![false; f_num_cells],
+            air_density: 0.,
+            sponges: vec![],
+            cell_porosity: vec![1.; f_num_cells],
+            cell_permeability: vec![f32::INFINITY; f_num_cells],
+            particle_absorbed: vec![false; max_particles],
+            squeeze: 0.,
+            sediment: None,
+            bed_height: vec![f32::default(); f_num_x],
+            bed_cell: vec![false; f_num_cells],
+            particle_sediment: vec![f32::default(); max_particles],
+            gas: None,
+            gas_sources: vec![],
+            smoke_density: vec![f32::default(); f_num_cells],
+            cell_temperature: vec![AMBIENT_TEMPERATURE; f_num_cells],
+            moving_tank: None,
+            tank_cell: vec![false; f_num_cells],
+            obstacles: vec![],
+            wall_impulses: [LoadAccumulator::default(); 4],
+            obstacle_impulses: vec![],
+            solid_loads: vec![],
+            color_mode: ColorMode::default(),
         }
     }
 
+    // Fills a block in the lower left corner of the tank, inside the moving tank if there is one
     pub fn with_particles(mut self, num_x: usize, num_y: usize) -> Self {
         self.num_particles = num_y * num_x;
 
@@ -148,9 +529,16 @@ impl FlipFluid {
         let mut p = 0;
         for i in 0..num_x {
             for j in 0..num_y {
-                self.particle_pos[p] = h + r + dx * i as f32 + if j % 2 == 0 { 0. } else { r };
+                let mut pos = Vec2::new(
+                    h + r + dx * i as f32 + if j % 2 == 0 { 0. } else { r },
+                    h + r + dy * j as f32,
+                );
+                if let Some(tank) = self.moving_tank {
+                    pos = tank.to_world(pos);
+                }
+                self.particle_pos[p] = pos.x;
                 p += 1;
-                self.particle_pos[p] = h + r + dy * j as f32;
+                self.particle_pos[p] = pos.y;
                 p += 1;
             }
         }
@@ -172,6 +560,267 @@ impl FlipFluid {
         self
     }
 
+    // Fills the cells above the pointed top of the tank, so the grid has the same bottle neck
+    // the particles are kept under
+    pub fn with_bottle_neck(mut self) -> Self {
+        let n = self.f_num_y;
+        let h = self.h;
+        let max = self.particle_max();
+
+        for i in 0..self.f_num_x {
+            // Highest point of the neck over the cell
+            let x = (max.x * 0.5).clamp(i as f32 * h, (i + 1) as f32 * h);
+            let neck_y = self.particle_max_y(x);
+            for j in 0..n {
+                if j as f32 * h >= neck_y {
+                    self.s[i * n + j] = 0.;
+                }
+            }
+        }
+
+        self
+    }
+
+    // Particles with fewer neighbours than min_neighbours are integrated ballistically
+    pub fn with_ballistic_droplets(mut self, min_neighbours: usize, air_drag: f32) -> Self {
+        self.ballistic_min_neighbours = min_neighbours;
+        self.air_drag = air_drag;
+        self
+    }
+
+    pub fn with_vorticity_confinement(mut self, strength: f32) -> Self {
+        self.vorticity_confinement = strength;
+        self
+    }
+
+    pub fn with_advection(mut self, advection: Advection) -> Self {
+        self.advection = advection;
+        self
+    }
+
+    pub fn set_rheology(&mut self, rheology: Option<Rheology>) {
+        self.rheology = rheology;
+    }
+
+    // Simulates sand like material with Drucker-Prager friction, see
+    // "Animating Sand as a Fluid" (Zhu and Bridson 2005).
+    pub fn set_granular(&mut self, angle_of_repose: Option<f32>) {
+        self.granular = angle_of_repose;
+    }
+
+    // Thermal expansion is the relative density change per degree
+    pub fn with_heat_transfer(mut self, diffusivity: f32, expansion: f32) -> Self {
+        self.thermal_diffusivity = diffusivity;
+        self.thermal_expansion = expansion;
+        self
+    }
+
+    pub fn with_wall_temperature(mut self, wall: Wall, temperature: f32) -> Self {
+        self.wall_temperature[wall as usize] = Some(temperature);
+        self
+    }
+
+    pub fn with_wall_wetting(mut self, wall: Wall, wetting: Wetting) -> Self {
+        self.wall_wetting[wall as usize] = Some(wetting);
+        self
+    }
+
+    pub fn with_phase_change(mut self, freezing_point: f32) -> Self {
+        self.freezing_point = Some(freezing_point);
+        self
+    }
+
+    pub fn with_ice_wetting(mut self, wetting: Wetting) -> Self {
+        self.ice_wetting = Some(wetting);
+        self
+    }
+
+    // Sets the temperature of all particles inside the rectangle, e.g. to freeze an ice cube
+    pub fn with_temperature_block(mut self, min: Vec2, max: Vec2, temperature: f32) -> Self {
+        for i in 0..self.num_particles {
+            let pos = self.position(i);
+            if pos.cmpge(min).all() && pos.cmple(max).all() {
+                self.particle_temperature[i] = temperature;
+            }
+        }
+        self.update_phase();
+        self
+    }
+
+    // Fills the space around the liquid with air particles, up to the particle capacity.
+    // Density is relative to the liquid.
+    pub fn with_air_particles(mut self, density: f32) -> Self {
+        self.air_density = density;
+        self.update_particle_cells();
+
+        // Particles are pushed apart to this distance anyway
+        let dx = 4. * self.particle_radius;
+        let dy = 3_f32.sqrt() / 2.0 * dx;
+        let (min, max) = (Vec2::splat(self.h), self.particle_max());
+        let num_liquid = self.num_particles;
+
+        let mut j = 0;
+        while min.y + dy * j as f32 <= max.y {
+            let mut i = 0;
+            loop {
+                let offset = if j % 2 == 0 { 0. } else { 0.5 * dx };
+                let pos = min + Vec2::new(dx * i as f32 + offset, dy * j as f32);
+                i += 1;
+
+                if pos.x > max.x {
+                    break;
+                }
+                if pos.y > self.particle_max_y(pos.x)
+                    || pos.y < min.y + self.bed_height[self.column_index(pos.x)]
+                    || self.has_particle_within(pos, 0.5 * dx, num_liquid)
+                {
+                    continue;
+                }
+                if self.num_particles >= self.max_particles {
+                    return self;
+                }
+
+                let p = self.num_particles;
+                self.particle_pos[2 * p] = pos.x;
+                self.particle_pos[2 * p + 1] = pos.y;
+                self.particle_air[p] = true;
+                self.num_particles += 1;
+            }
+            j += 1;
+        }
+
+        self
+    }
+
+    pub fn is_air(&self, i: usize) -> bool {
+        self.particle_air[i]
+    }
+
+    // Liquid free to move, neither air nor frozen nor soaked up by a sponge
+    pub fn is_liquid(&self, i: usize) -> bool {
+        !self.particle_air[i] && !self.is_immobile(i)
+    }
+
+    pub fn with_sponge(mut self, sponge: Sponge) -> Self {
+        let n = self.f_num_y;
+        let h = self.h;
+
+        for i in 0..self.f_num_x {
+            for j in 0..self.f_num_y {
+                let center = Vec2::new(i as f32 + 0.5, j as f32 + 0.5) * h;
+                if sponge.contains(center) && self.s[i * n + j] != 0. {
+                    self.cell_porosity[i * n + j] = sponge.porosity;
+                    self.cell_permeability[i * n + j] = sponge.permeability;
+                    self.s[i * n + j] = sponge.porosity;
+                }
+            }
+        }
+
+        self.sponges.push(sponge);
+        self
+    }
+
+    // Squeezed sponges only hold (1 - squeeze) of their capacity and release the rest
+    pub fn set_squeeze(&mut self, squeeze: f32) {
+        self.squeeze = squeeze.clamp(0., 1.);
+    }
+
+    // Covers the floor of the tank with an erodible bed
+    pub fn with_sediment_bed(mut self, height: f32, sediment: Sediment) -> Self {
+        self.sediment = Some(sediment);
+        self.bed_height[1..self.f_num_x - 1].fill(height);
+        self.update_bed_cells();
+        self
+    }
+
+    // Top of the sediment bed at the column centers, empty without a bed
+    pub fn bed_surface(&self) -> Vec<Vec2> {
+        if self.sediment.is_none() {
+            return vec![];
+        }
+
+        (1..self.f_num_x - 1)
+            .map(|i| Vec2::new((i as f32 + 0.5) * self.h, self.h + self.bed_height[i]))
+            .collect()
+    }
+
+    pub fn with_gas_source(mut self, source: GasSource) -> Self {
+        self.gas_sources.push(source);
+        self
+    }
+
+    pub fn set_gas(&mut self, gas: Option<Gas>) {
+        self.gas = gas;
+    }
+
+    pub fn is_gas(&self) -> bool {
+        self.gas.is_some()
+    }
+
+    pub fn with_obstacle(mut self, obstacle: Obstacle) -> Self {
+        let n = self.f_num_y;
+        let h = self.h;
+
+        for i in 1..self.f_num_x - 1 {
+            for j in 1..self.f_num_y - 1 {
+                let center = (Vec2::new(i as f32, j as f32) + 0.5) * h;
+                if obstacle.contains(center) {
+                    self.s[i * n + j] = 0.;
+                    self.cell_porosity[i * n + j] = 0.;
+                }
+            }
+        }
+
+        self.obstacles.push(obstacle);
+        self
+    }
+
+    pub fn obstacles(&self) -> &[Obstacle] {
+        &self.obstacles
+    }
+
+    // Switches to the world-frame mode, particles must be given in grid coordinates
+    pub fn with_moving_tank(mut self, tank: MovingTank) -> Self {
+        self.moving_tank = Some(tank);
+        self.update_tank_cells();
+        self
+    }
+
+    pub fn set_tank_motion(
+        &mut self,
+        center: Vec2,
+        angle: f32,
+        linear_velocity: Vec2,
+        angular_velocity: f32,
+    ) {
+        if let Some(tank) = &mut self.moving_tank {
+            tank.center = center;
+            tank.angle = angle;
+            tank.linear_velocity = linear_velocity;
+            tank.angular_velocity = angular_velocity;
+        }
+    }
+
+    pub fn moving_tank(&self) -> Option<MovingTank> {
+        self.moving_tank
+    }
+
+    pub fn set_color_mode(&mut self, color_mode: ColorMode) {
+        self.color_mode = color_mode;
+    }
+
+    pub fn color_mode(&self) -> ColorMode {
+        self.color_mode
+    }
+
+    pub fn set_advection(&mut self, advection: Advection) {
+        self.advection = advection;
+    }
+
+    pub fn advection(&self) -> Advection {
+        self.advection
+    }
+
     pub fn simulate(
         &mut self,
         dt: f32,
@@ -187,12 +836,29 @@ impl FlipFluid {
         over_relaxation: f32,
         compensate_drift: bool,
         separate_particles: bool,
-        gizmos: &mut Gizmos,
     ) {
+        self.wall_impulses = [LoadAccumulator::default(); 4];
+        self.obstacle_impulses = vec![LoadAccumulator::default(); self.obstacles.len()];
+        self.solid_loads.clear();
+
+        if let Some(gas) = self.gas {
+            self.simulate_gas(
+                dt,
+                gas,
+                Vec2::new(tank_accel_x, tank_accel_y),
+                num_pressure_iters,
+                over_relaxation,
+            );
+            return;
+        }
+
         let num_sub_steps = 1;
         let std = dt / num_sub_steps as f32;
 
         for _ in 0..num_sub_steps {
+            if self.moving_tank.is_some() {
+                self.update_tank_cells();
+            }
             self.integrate_particles(
                 std,
                 tank_accel_x,
@@ -201,23 +867,84 @@ impl FlipFluid {
                 angular_velocity,
                 rotation_center_x,
                 rotation_center_y,
-                gizmos,
             );
+            if self.has_wetting() {
+                self.apply_wetting(std);
+            }
             if separate_particles {
                 self.push_particles_apart(num_particle_iters);
             }
+            if self.ballistic_min_neighbours > 0 {
+                if !separate_particles {
+                    self.update_particle_cells();
+                }
+                self.update_ballistic_particles();
+            }
             self.handle_particle_collision();
+            if !self.sponges.is_empty() {
+                self.absorb_particles();
+            }
+            if self.thermal_diffusivity > 0. || self.wall_temperature.iter().any(Option::is_some) {
+                self.transfer_heat(std);
+            }
+            if self.freezing_point.is_some() {
+                self.update_phase();
+            }
+            if let Some(sediment) = self.sediment {
+                self.transport_sediment(std, sediment);
+            }
             self.transfer_velocities(None);
+            if let Some(tank) = self.moving_tank {
+                self.apply_tank_velocities(tank);
+            }
+            self.extrapolate_velocities();
+            self.prev_u = self.u.clone();
+            self.prev_v = self.v.clone();
             self.update_particle_density();
+            if self.vorticity_confinement > 0. {
+                self.apply_vorticity_confinement(std);
+            }
+            if let Some(rheology) = self.rheology {
+                self.apply_viscosity(std, rheology);
+            }
+            if !self.sponges.is_empty() {
+                self.apply_darcy_drag(std);
+            }
             self.solve_incompressibility(
                 num_pressure_iters,
                 std,
                 over_relaxation,
                 compensate_drift,
             );
+            self.add_pressure_impulses(std);
+            if let Some(angle_of_repose) = self.granular {
+                self.apply_granular_friction(std, angle_of_repose);
+            }
+            self.extrapolate_velocities();
+            let flip_ratio = if self.granular.is_some() {
+                flip_ratio.min(GRANULAR_FLIP_RATIO)
+            } else {
+                flip_ratio
+            };
             self.transfer_velocities(Some(flip_ratio));
+            if self.advection != Advection::Euler {
+                self.advect_particles(std);
+                self.handle_particle_collision();
+            }
         }
 
+        self.solid_loads = Wall::ALL
+            .iter()
+            .zip(&self.wall_impulses)
+            .map(|(wall, impulses)| (Solid::Wall(*wall), impulses.load(dt)))
+            .chain(
+                self.obstacle_impulses
+                    .iter()
+                    .enumerate()
+                    .map(|(k, impulses)| (Solid::Obstacle(k), impulses.load(dt))),
+            )
+            .collect();
+
         self.update_particle_colors();
     }
 
@@ -233,39 +960,429 @@ impl FlipFluid {
         )
     }
 
-    // Pass in gravity vec, linear_accel, angular_vel, angular_accel, center_or_rotation separately.
-    fn integrate_particles(
-        &mut self,
-        dt: f32,
-        accel_x: f32,
-        accel_y: f32,
-        angular_accel: f32,
-        angular_velocity: f32,
-        rotation_center_x: f32,
-        rotation_center_y: f32,
-        gizmos: &mut Gizmos,
-    ) {
-        let angular_velocity_2 = angular_velocity * angular_velocity;
+    pub fn num_particles(&self) -> usize {
+        self.num_particles
+    }
 
-        for i in 0..self.num_particles {
-            let dx = rotation_center_x - self.particle_pos[2 * i];
-            let dy = rotation_center_y - self.particle_pos[2 * i + 1];
-            let r = (dx * dx + dy * dy).sqrt();
+    pub fn velocity(&self, i: usize) -> Vec2 {
+        Vec2::new(self.particle_vel[2 * i], self.particle_vel[2 * i + 1])
+    }
 
-            if r > f32::EPSILON && angular_accel.abs() > f32::EPSILON {
-                let tangent = Vec2::new(-dy, dx).normalize() * angular_accel * r;
+    // Overwrites a particle, e.g. when handing particles back from another solver
+    pub fn set_particle(&mut self, i: usize, pos: Vec2, vel: Vec2) {
+        self.particle_pos[2 * i] = pos.x;
+        self.particle_pos[2 * i + 1] = pos.y;
+        self.particle_vel[2 * i] = vel.x;
+        self.particle_vel[2 * i + 1] = vel.y;
+    }
 
-                self.particle_vel[2 * i] += dt * tangent.x;
-                self.particle_vel[2 * i + 1] += dt * tangent.y;
-            }
+    // Smoke color of a cell, transparent where there is no smoke
+    pub fn cell_color(&self, cell_nr: usize) -> Color {
+        Color::srgba(
+            self.cell_color[3 * cell_nr],
+            self.cell_color[3 * cell_nr + 1],
+            self.cell_color[3 * cell_nr + 2],
+            self.smoke_density[cell_nr].clamp(0., 1.),
+        )
+    }
 
-            if r > f32::EPSILON {
-                let centripetal_accel =
-                    Vec2::new(dx, dy).normalize().neg() * angular_velocity_2 * r;
-                self.particle_vel[2 * i] += dt * centripetal_accel.x;
-                self.particle_vel[2 * i + 1] += dt * centripetal_accel.y;
+    // Mass properties of liquid and air in tank space, angular quantities are about point
+    pub fn mass_properties(&self, point: Vec2) -> MassProperties {
+        MassProperties::from_particles(
+            (0..self.num_particles).map(|i| {
+                (
+                    self.position(i),
+                    self.velocity(i),
+                    self.particle_mass(i),
+                    self.particle_area(),
+                )
+            }),
+            point,
+        )
+    }
 
-                // gizmos.arrow_2d(
+    // Same in world space, point is in world space too. The entity moves with linear velocity
+    // and turns with angular velocity around its translation, which carries the particles along.
+    pub fn world_mass_properties(
+        &self,
+        point: Vec2,
+        transform: &GlobalTransform,
+        linear_velocity: Vec2,
+        angular_velocity: f32,
+    ) -> MassProperties {
+        let offset = self.entity_offset();
+        let center = transform.translation().xy();
+        MassProperties::from_particles(
+            (0..self.num_particles).map(|i| {
+                let position = transform
+                    .transform_point((self.position(i) + offset).extend(0.))
+                    .xy();
+                let velocity = transform
+                    .affine()
+                    .transform_vector3(self.velocity(i).extend(0.))
+                    .xy()
+                    + linear_velocity
+                    + (position - center).perp() * angular_velocity;
+                (
+                    position,
+                    velocity,
+                    self.particle_mass(i),
+                    self.particle_area(),
+                )
+            }),
+            point,
+        )
+    }
+
+    // solid_loads in world space, with the torques about the entity translation
+    pub fn world_solid_loads(&self, transform: &GlobalTransform) -> Vec<(Solid, Load)> {
+        let offset = self.entity_offset();
+        self.solid_loads()
+            .iter()
+            .map(|(solid, load)| {
+                let load = Load {
+                    force: transform
+                        .affine()
+                        .transform_vector3(load.force.extend(0.))
+                        .xy(),
+                    torque: load.torque + offset.perp_dot(load.force),
+                    center_of_pressure: transform
+                        .transform_point((load.center_of_pressure + offset).extend(0.))
+                        .xy(),
+                };
+                (*solid, load)
+            })
+            .collect()
+    }
+
+    // From tank space to the space of the entity. The tank is centered on the entity, while
+    // a world-frame grid starts at it.
+    fn entity_offset(&self) -> Vec2 {
+        if self.moving_tank.is_some() {
+            Vec2::ZERO
+        } else {
+            self.size * -0.5
+        }
+    }
+
+    // Force and torque about point the liquid exerted on the tank during the last step, the
+    // solid_loads summed up. Linear acceleration is the one the liquid was simulated with, the
+    // part of the loads that carried the liquid along with the accelerating tank frame is taken
+    // out again.
+    pub fn tank_reaction(
+        &self,
+        point: Vec2,
+        gravity: Vec2,
+        linear_acceleration: Vec2,
+        angular_acceleration: f32,
+    ) -> (Vec2, f32) {
+        let properties = self.mass_properties(point);
+        let (force, torque) =
+            self.solid_loads
+                .iter()
+                .fold((Vec2::ZERO, 0.), |(force, torque), (_, load)| {
+                    (
+                        force + load.force,
+                        torque + load.torque - point.perp_dot(load.force),
+                    )
+                });
+
+        (
+            force + properties.mass * (gravity - linear_acceleration),
+            torque + properties.moment_of_inertia * angular_acceleration,
+        )
+    }
+
+    // Load on every wall and obstacle during the last step in tank space, from the pressure
+    // on their faces and the ballistic particles bouncing off them. Torques are about the origin.
+    pub fn solid_loads(&self) -> &[(Solid, Load)] {
+        &self.solid_loads
+    }
+
+    // Solid a cell belongs to, if it is one the loads are reported for
+    fn solid_at(&self, i: usize, j: usize) -> Option<Solid> {
+        let cell_nr = i * self.f_num_y + j;
+        if self.s[cell_nr] != 0. {
+            return None;
+        }
+
+        let center = (Vec2::new(i as f32, j as f32) + 0.5) * self.h;
+        if let Some(k) = self
+            .obstacles
+            .iter()
+            .position(|obstacle| obstacle.contains(center))
+        {
+            return Some(Solid::Obstacle(k));
+        }
+
+        if let Some(tank) = self.moving_tank.filter(|_| self.tank_cell[cell_nr]) {
+            // Nearest wall of the tank
+            let local = tank.to_local(center);
+            let distances = [
+                local.x,
+                tank.size.x - local.x,
+                local.y,
+                tank.size.y - local.y,
+            ];
+            let wall = (0..4).min_by(|a, b| distances[*a].total_cmp(&distances[*b]));
+            return wall.map(|wall| Solid::Wall(Wall::ALL[wall]));
+        }
+
+        if i == 0 {
+            Some(Solid::Wall(Wall::Left))
+        } else if i == self.f_num_x - 1 {
+            Some(Solid::Wall(Wall::Right))
+        } else if j == 0 || self.bed_cell[cell_nr] {
+            Some(Solid::Wall(Wall::Bottom))
+        } else if j == self.f_num_y - 1 || center.y > self.particle_max_y(center.x) {
+            Some(Solid::Wall(Wall::Top))
+        } else {
+            None
+        }
+    }
+
+    fn has_wetting(&self) -> bool {
+        self.wall_wetting.iter().any(Option::is_some)
+            || self.ice_wetting.is_some()
+            || self
+                .obstacles
+                .iter()
+                .any(|obstacle| obstacle.wetting.is_some())
+            || self.sponges.iter().any(|sponge| sponge.wetting.is_some())
+            || self
+                .sediment
+                .is_some_and(|sediment| sediment.wetting.is_some())
+    }
+
+    // Wetting of the solid or porous cell, the bed and the bottle neck are smooth surfaces
+    // that apply_wetting handles on their own
+    fn wetting_at(&self, i: usize, j: usize) -> Option<Wetting> {
+        let cell_nr = i * self.f_num_y + j;
+        if self.ice_cell[cell_nr] {
+            return self.ice_wetting;
+        }
+        if self.bed_cell[cell_nr] {
+            return None;
+        }
+        if self.s[cell_nr] != 0. {
+            let center = (Vec2::new(i as f32, j as f32) + 0.5) * self.h;
+            return self
+                .sponges
+                .iter()
+                .find(|sponge| sponge.contains(center))
+                .and_then(|sponge| sponge.wetting);
+        }
+
+        match self.solid_at(i, j)? {
+            Solid::Wall(wall) => self.wall_wetting[wall as usize],
+            Solid::Obstacle(k) => self.obstacles[k].wetting,
+        }
+    }
+
+    // Particles on the grid push on the solids through the pressure, their collisions are
+    // already in there. Only the ballistic ones skip the grid and hit the solids directly.
+    fn add_collision_impulse(&mut self, i: usize, solid: Solid, point: Vec2, impulse: Vec2) {
+        if self.particle_ballistic[i] {
+            self.add_impulse(solid, point, impulse);
+        }
+    }
+
+    fn add_impulse(&mut self, solid: Solid, point: Vec2, impulse: Vec2) {
+        let impulses = match solid {
+            Solid::Wall(wall) => &mut self.wall_impulses[wall as usize],
+            Solid::Obstacle(k) => &mut self.obstacle_impulses[k],
+        };
+        impulses.add(point, impulse);
+    }
+
+    // Pressure of the fluid cells on the faces they share with solids. The grid counts partly
+    // filled cells as full, so the pressure of a cell stands for a column of full cells up to
+    // the surface. It is scaled down by how full that column really is.
+    fn add_pressure_impulses(&mut self, dt: f32) {
+        let n = self.f_num_y;
+        let h = self.h;
+
+        let mut fill = vec![0.; self.f_num_cells];
+        for i in 0..self.num_particles {
+            if self.particle_ballistic[i]
+                || self.particle_frozen[i]
+                || self.particle_absorbed[i]
+                || self.particle_air[i]
+            {
+                continue;
+            }
+            fill[self.cell_index(self.position(i))] += self.particle_area() / (h * h);
+        }
+
+        for i in 1..self.f_num_x - 1 {
+            let mut column_fill = 0.;
+            let mut column_cells = 0.;
+            for j in (1..self.f_num_y - 1).rev() {
+                if self.cell_type[i * n + j] != FLUID_CELL {
+                    column_fill = 0.;
+                    column_cells = 0.;
+                    continue;
+                }
+                column_fill += fill[i * n + j].min(1.);
+                column_cells += 1.;
+
+                let center = (Vec2::new(i as f32, j as f32) + 0.5) * h;
+                let pressure = self.p[i * n + j] * column_fill / column_cells;
+                for (di, dj, normal) in [
+                    (-1, 0, Vec2::NEG_X),
+                    (1, 0, Vec2::X),
+                    (0, -1, Vec2::NEG_Y),
+                    (0, 1, Vec2::Y),
+                ] {
+                    let ni = (i as i32 + di) as usize;
+                    let nj = (j as i32 + dj) as usize;
+                    if let Some(solid) = self.solid_at(ni, nj) {
+                        let face = center + normal * 0.5 * h;
+                        self.add_impulse(solid, face, normal * pressure * h * dt);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn spacing(&self) -> f32 {
+        self.h
+    }
+
+    // Number of cols and rows of grid cells
+    pub fn grid_size(&self) -> (usize, usize) {
+        (self.f_num_x, self.f_num_y)
+    }
+
+    pub fn particle_radius(&self) -> f32 {
+        self.particle_radius
+    }
+
+    // Bilinear interpolation of the staggered grid velocities at a point in tank space.
+    pub fn sample_velocity(&self, point: Vec2) -> Vec2 {
+        Vec2::new(
+            self.sample_velocity_component(point, 0),
+            self.sample_velocity_component(point, 1),
+        )
+    }
+
+    // Particle density of the cell containing point, relative to the rest density.
+    pub fn relative_density(&self, point: Vec2) -> f32 {
+        if self.particle_rest_density == 0. {
+            return 0.;
+        }
+
+        self.particle_density[self.cell_index(point)] / self.particle_rest_density
+    }
+
+    // Outward pointing normal from the fluid density gradient, if near the surface.
+    // Solid cells are skipped, so walls don't count as a surface.
+    pub fn surface_normal(&self, point: Vec2) -> Option<Vec2> {
+        let h = self.h;
+        let center = self.relative_density(point);
+        let sample = |offset: Vec2| {
+            if self.is_solid(point + offset) {
+                center
+            } else {
+                self.relative_density(point + offset)
+            }
+        };
+
+        let gradient = Vec2::new(
+            sample(Vec2::X * h) - sample(Vec2::NEG_X * h),
+            sample(Vec2::Y * h) - sample(Vec2::NEG_Y * h),
+        ) / (2. * h);
+
+        if gradient.length_squared() * h * h < 0.01 {
+            return None;
+        }
+
+        Some(-gradient.normalize())
+    }
+
+    pub fn is_solid(&self, point: Vec2) -> bool {
+        self.s[self.cell_index(point)] == 0.
+    }
+
+    fn cell_index(&self, point: Vec2) -> usize {
+        let h1 = self.f_inv_spacing;
+        let xi = ((point.x * h1).floor().max(0.) as usize).min(self.f_num_x - 1);
+        let yi = ((point.y * h1).floor().max(0.) as usize).min(self.f_num_y - 1);
+        xi * self.f_num_y + yi
+    }
+
+    fn sample_velocity_component(&self, point: Vec2, component: usize) -> f32 {
+        let h2 = 0.5 * self.h;
+
+        let dx = if component == 0 { 0.0 } else { h2 };
+        let dy = if component == 0 { h2 } else { 0.0 };
+        let f = if component == 0 { &self.u } else { &self.v };
+
+        self.sample_field(f, point, dx, dy)
+    }
+
+    // Bilinear interpolation of a grid field whose samples are offset by (dx, dy) in each cell
+    fn sample_field(&self, f: &[f32], point: Vec2, dx: f32, dy: f32) -> f32 {
+        let n = self.f_num_y;
+        let h = self.h;
+        let h1 = self.f_inv_spacing;
+
+        let x = point.x.clamp(h, (self.f_num_x as f32 - 1.) * h);
+        let y = point.y.clamp(h, (self.f_num_y as f32 - 1.) * h);
+
+        let x0 = ((x - dx) * h1).floor().min(self.f_num_x as f32 - 2.);
+        let tx = ((x - dx) - x0 * h) * h1;
+        let x1 = (x0 + 1.).min(self.f_num_x as f32 - 2.);
+
+        let y0 = ((y - dy) * h1).floor().min(self.f_num_y as f32 - 2.);
+        let ty = ((y - dy) - y0 * h) * h1;
+        let y1 = (y0 + 1.).min(self.f_num_y as f32 - 2.);
+
+        let sx = 1.0 - tx;
+        let sy = 1.0 - ty;
+
+        sx * sy * f[x0 as usize * n + y0 as usize]
+            + tx * sy * f[x1 as usize * n + y0 as usize]
+            + tx * ty * f[x1 as usize * n + y1 as usize]
+            + sx * ty * f[x0 as usize * n + y1 as usize]
+    }
+
+    // Pass in gravity vec, linear_accel, angular_vel, angular_accel, center_or_rotation separately.
+    fn integrate_particles(
+        &mut self,
+        dt: f32,
+        accel_x: f32,
+        accel_y: f32,
+        angular_accel: f32,
+        angular_velocity: f32,
+        rotation_center_x: f32,
+        rotation_center_y: f32,
+    ) {
+        let angular_velocity_2 = angular_velocity * angular_velocity;
+
+        for i in 0..self.num_particles {
+            if self.is_immobile(i) {
+                continue;
+            }
+
+            let dx = rotation_center_x - self.particle_pos[2 * i];
+            let dy = rotation_center_y - self.particle_pos[2 * i + 1];
+            let r = (dx * dx + dy * dy).sqrt();
+
+            if r > f32::EPSILON && angular_accel.abs() > f32::EPSILON {
+                let tangent = Vec2::new(-dy, dx).normalize() * angular_accel * r;
+
+                self.particle_vel[2 * i] += dt * tangent.x;
+                self.particle_vel[2 * i + 1] += dt * tangent.y;
+            }
+
+            if r > f32::EPSILON {
+                let centripetal_accel =
+                    Vec2::new(dx, dy).normalize().neg() * angular_velocity_2 * r;
+                self.particle_vel[2 * i] += dt * centripetal_accel.x;
+                self.particle_vel[2 * i + 1] += dt * centripetal_accel.y;
+
+                // gizmos.arrow_2d(
                 //     Vec2::ZERO,
                 //     Vec2::new(dt * -centripetal_accel.x,  dt * -centripetal_accel.y) * 50.,
                 //     YELLOW,
@@ -278,17 +1395,52 @@ impl FlipFluid {
                 // );
             }
 
-            self.particle_vel[2 * i] += dt * (accel_x);
-            self.particle_vel[2 * i + 1] += dt * (accel_y);
+            // Warmer particles are lighter and rise against the apparent gravity
+            let buoyancy =
+                1. - self.thermal_expansion * (self.particle_temperature[i] - AMBIENT_TEMPERATURE);
+            self.particle_vel[2 * i] += dt * (accel_x) * buoyancy;
+            self.particle_vel[2 * i + 1] += dt * (accel_y) * buoyancy;
+
+            if self.particle_ballistic[i] {
+                let drag = (1. - self.air_drag * dt).max(0.);
+                self.particle_vel[2 * i] *= drag;
+                self.particle_vel[2 * i + 1] *= drag;
+            }
 
-            self.particle_pos[2 * i] += self.particle_vel[2 * i] * dt;
-            self.particle_pos[2 * i + 1] += self.particle_vel[2 * i + 1] * dt;
+            // Otherwise moved through the grid velocity after the pressure solve
+            if self.advection == Advection::Euler || self.particle_ballistic[i] {
+                self.particle_pos[2 * i] += self.particle_vel[2 * i] * dt;
+                self.particle_pos[2 * i + 1] += self.particle_vel[2 * i + 1] * dt;
+            }
         }
     }
 
-    fn push_particles_apart(&mut self, num_iters: usize) {
-        let color_diffusion_coeff = 0.001;
+    // Only moves the particles, their velocities are updated by the FLIP transfer.
+    fn advect_particles(&mut self, dt: f32) {
+        for i in 0..self.num_particles {
+            if self.particle_ballistic[i] || self.is_immobile(i) {
+                continue;
+            }
+
+            let pos = self.position(i);
+            let k1 = self.sample_velocity(pos);
+            let k2 = self.sample_velocity(pos + 0.5 * dt * k1);
+
+            let next_pos = match self.advection {
+                Advection::Euler => pos + dt * k1,
+                Advection::RungeKutta2 => pos + dt * k2,
+                Advection::RungeKutta3 => {
+                    let k3 = self.sample_velocity(pos + 0.75 * dt * k2);
+                    pos + dt * (2. / 9. * k1 + 3. / 9. * k2 + 4. / 9. * k3)
+                }
+            };
+
+            self.particle_pos[2 * i] = next_pos.x;
+            self.particle_pos[2 * i + 1] = next_pos.y;
+        }
+    }
 
+    fn update_particle_cells(&mut self) {
         // count particles per cell
 
         self.num_cell_particles.fill(0);
@@ -325,6 +1477,65 @@ impl FlipFluid {
             self.first_cell_particle[cell_nr] -= 1;
             self.cell_particle_ids[self.first_cell_particle[cell_nr]] = i;
         }
+    }
+
+    fn update_ballistic_particles(&mut self) {
+        // Particles in the bulk are pushed 4r apart, count neighbours a little further out
+        let max_dist = 5. * self.particle_radius;
+        let max_dist_2 = max_dist * max_dist;
+        let reach = (max_dist * self.p_inv_spacing).ceil() as i32;
+
+        for i in 0..self.num_particles {
+            // Air fills the tank around the liquid and always goes through the grid solve
+            if self.particle_air[i] {
+                self.particle_ballistic[i] = false;
+                continue;
+            }
+
+            let px = self.particle_pos[2 * i];
+            let py = self.particle_pos[2 * i + 1];
+
+            let pxi = (px * self.p_inv_spacing).floor() as i32;
+            let pyi = (py * self.p_inv_spacing).floor() as i32;
+            let x0 = (pxi - reach).max(0) as usize;
+            let y0 = (pyi - reach).max(0) as usize;
+            let x1 = ((pxi + reach).max(0) as usize).min(self.p_num_x - 1);
+            let y1 = ((pyi + reach).max(0) as usize).min(self.p_num_y - 1);
+
+            let mut num_neighbours = 0;
+
+            'cells: for xi in x0..=x1 {
+                for yi in y0..=y1 {
+                    let cell_nr = xi * self.p_num_y + yi;
+                    let first = self.first_cell_particle[cell_nr];
+                    let last = self.first_cell_particle[cell_nr + 1];
+                    for j in first..last {
+                        let id = self.cell_particle_ids[j];
+                        if id == i || self.particle_air[id] {
+                            continue;
+                        }
+
+                        let dx = self.particle_pos[2 * id] - px;
+                        let dy = self.particle_pos[2 * id + 1] - py;
+                        if dx * dx + dy * dy <= max_dist_2 {
+                            num_neighbours += 1;
+                            if num_neighbours >= self.ballistic_min_neighbours {
+                                break 'cells;
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Particles landing back in the liquid gain neighbours and rejoin the grid solve
+            self.particle_ballistic[i] = num_neighbours < self.ballistic_min_neighbours;
+        }
+    }
+
+    fn push_particles_apart(&mut self, num_iters: usize) {
+        let color_diffusion_coeff = 0.001;
+
+        self.update_particle_cells();
 
         // push particles apart
 
@@ -364,14 +1575,27 @@ impl FlipFluid {
                             if d2 > min_dist_2 || d2 == 0. {
                                 continue;
                             }
+                            // Absorbed particles sit in the pores and don't take up space
+                            if self.particle_absorbed[i] || self.particle_absorbed[id] {
+                                continue;
+                            }
+
+                            // Ice does not move, the liquid particle takes the full correction
+                            let (wi, wid) =
+                                match (self.particle_frozen[i], self.particle_frozen[id]) {
+                                    (true, true) => continue,
+                                    (true, false) => (0., 2.),
+                                    (false, true) => (2., 0.),
+                                    (false, false) => (1., 1.),
+                                };
                             let d = d2.sqrt();
                             let s = 0.5 * (min_dist - d) / d;
                             dx *= s;
                             dy *= s;
-                            self.particle_pos[2 * i] -= dx;
-                            self.particle_pos[2 * i + 1] -= dy;
-                            self.particle_pos[2 * id] += dx;
-                            self.particle_pos[2 * id + 1] += dy;
+                            self.particle_pos[2 * i] -= dx * wi;
+                            self.particle_pos[2 * i + 1] -= dy * wi;
+                            self.particle_pos[2 * id] += dx * wid;
+                            self.particle_pos[2 * id + 1] += dy * wid;
 
                             // diffuse colors
 
@@ -391,89 +1615,706 @@ impl FlipFluid {
         }
     }
 
-    fn handle_particle_collision(&mut self) {
-        let h = 1. / self.f_inv_spacing;
-        let r = self.particle_radius;
-        let min_x = h + r;
-        let max_x = (self.f_num_x - 1) as f32 * h - r * 4.;
-        let min_y = h + r;
-        let max_y = (self.f_num_y - 1) as f32 * h - r * 4.;
+    fn transfer_heat(&mut self, dt: f32) {
+        self.update_particle_cells();
 
-        let cap_y = |x: f32| -> f32 { -((x - max_x * 0.5) * 0.5).abs() + max_y };
+        // Conduction between neighbouring particles, each pair exchanges heat once
+        let max_dist = 1. / self.p_inv_spacing;
+        let max_dist_2 = max_dist * max_dist;
+        let rate = (self.thermal_diffusivity * dt / max_dist_2).min(0.25);
 
         for i in 0..self.num_particles {
-            let mut x = self.particle_pos[2 * i];
-            let mut y = self.particle_pos[2 * i + 1];
+            // Air is too thin to carry heat between the liquid particles
+            if self.particle_air[i] {
+                continue;
+            }
 
-            let capped_y = cap_y(x);
+            let px = self.particle_pos[2 * i];
+            let py = self.particle_pos[2 * i + 1];
 
-            // wall collisions
-            if x < min_x {
-                x = min_x;
-                self.particle_vel[2 * i] = 0.;
-            } else if x > max_x {
-                x = max_x;
-                self.particle_vel[2 * i] = 0.;
-            }
+            let pxi = (px * self.p_inv_spacing).floor() as i32;
+            let pyi = (py * self.p_inv_spacing).floor() as i32;
+            let x0 = (pxi - 1).max(0) as usize;
+            let y0 = (pyi - 1).max(0) as usize;
+            let x1 = ((pxi + 1) as usize).min(self.p_num_x - 1);
+            let y1 = ((pyi + 1) as usize).min(self.p_num_y - 1);
 
-            if y < min_y {
-                y = min_y;
-                self.particle_vel[2 * i + 1] = 0.;
-            } else if y > capped_y {
-                y = capped_y;
-                self.particle_vel[2 * i + 1] = 0.;
+            for xi in x0..=x1 {
+                for yi in y0..=y1 {
+                    let cell_nr = xi * self.p_num_y + yi;
+                    let first = self.first_cell_particle[cell_nr];
+                    let last = self.first_cell_particle[cell_nr + 1];
+                    for j in first..last {
+                        let id = self.cell_particle_ids[j];
+                        if id <= i || self.particle_air[id] {
+                            continue;
+                        }
+
+                        let dx = self.particle_pos[2 * id] - px;
+                        let dy = self.particle_pos[2 * id + 1] - py;
+                        let d2 = dx * dx + dy * dy;
+                        if d2 > max_dist_2 {
+                            continue;
+                        }
+
+                        let weight = 1. - d2.sqrt() / max_dist;
+                        let delta = rate
+                            * weight
+                            * (self.particle_temperature[id] - self.particle_temperature[i]);
+                        self.particle_temperature[i] += delta;
+                        self.particle_temperature[id] -= delta;
+                    }
+                }
             }
+        }
 
-            self.particle_pos[2 * i] = x;
-            self.particle_pos[2 * i + 1] = y;
+        // Particles within one cell of a heated or cooled wall relax to its temperature
+        let h = self.h;
+        let max_x = (self.f_num_x - 1) as f32 * h;
+        let max_y = (self.f_num_y - 1) as f32 * h;
+        let wall_rate = (WALL_HEAT_TRANSFER * dt).min(1.);
+
+        for i in 0..self.num_particles {
+            let x = self.particle_pos[2 * i];
+            let y = self.particle_pos[2 * i + 1];
+            let near = [x < 2. * h, x > max_x - h, y < 2. * h, y > max_y - h];
+
+            for (wall, temperature) in self.wall_temperature.iter().enumerate() {
+                if let Some(temperature) = temperature {
+                    if near[wall] {
+                        self.particle_temperature[i] +=
+                            (temperature - self.particle_temperature[i]) * wall_rate;
+                    }
+                }
+            }
         }
     }
 
-    fn transfer_velocities(&mut self, flip_ratio: Option<f32>) {
-        let to_grid = flip_ratio.is_none();
+    // Frozen and absorbed particles keep their place and skip the grid transfers
+    fn is_immobile(&self, i: usize) -> bool {
+        self.particle_frozen[i] || self.particle_absorbed[i]
+    }
 
-        let n = self.f_num_y;
-        let h = self.h;
-        let h1 = self.f_inv_spacing;
-        let h2 = 0.5 * h;
+    fn absorb_particles(&mut self) {
+        for k in 0..self.sponges.len() {
+            let sponge = self.sponges[k];
+            let capacity = (sponge.capacity as f32 * (1. - self.squeeze)) as usize;
+            let inside = |fluid: &Self, i: usize| {
+                sponge.contains(fluid.position(i))
+                    && !fluid.particle_air[i]
+                    && !fluid.particle_frozen[i]
+            };
 
-        if to_grid {
-            self.prev_u = self.u.clone();
-            self.prev_v = self.v.clone();
+            let mut num_absorbed = (0..self.num_particles)
+                .filter(|&i| self.particle_absorbed[i] && inside(self, i))
+                .count();
 
-            self.du.fill(0.);
-            self.dv.fill(0.);
-            self.u.fill(0.);
-            self.v.fill(0.);
+            for i in 0..self.num_particles {
+                if !inside(self, i) {
+                    continue;
+                }
 
-            for i in 0..self.f_num_cells {
-                self.cell_type[i] = if self.s[i] == 0.0 {
-                    SOLID_CELL
-                } else {
-                    AIR_CELL
-                };
+                if self.particle_absorbed[i] && num_absorbed > capacity {
+                    self.particle_absorbed[i] = false;
+                    num_absorbed -= 1;
+                } else if !self.particle_absorbed[i] && num_absorbed < capacity {
+                    self.particle_absorbed[i] = true;
+                    self.particle_vel[2 * i] = 0.;
+                    self.particle_vel[2 * i + 1] = 0.;
+                    num_absorbed += 1;
+                }
             }
+        }
+    }
 
-            for i in 0..self.num_particles {
-                let x = self.particle_pos[2 * i];
-                let y = self.particle_pos[2 * i + 1];
-                let xi = ((x * h1).floor() as usize).clamp(0, self.f_num_x - 1);
-                let yi = ((y * h1).floor() as usize).clamp(0, self.f_num_y - 1);
-                let cell_nr = xi * n + yi;
-                if self.cell_type[cell_nr] == AIR_CELL {
-                    self.cell_type[cell_nr] = FLUID_CELL;
-                }
+    // Implicit Darcy drag on faces next to porous cells, dv/dt = -viscosity * porosity / k * v
+    fn apply_darcy_drag(&mut self, dt: f32) {
+        let n = self.f_num_y;
+        let viscosity = self
+            .rheology
+            .unwrap_or(Rheology::water())
+            .effective_viscosity(0.);
+
+        let drag = |fluid: &Self, a: usize, b: usize| {
+            let porosity = fluid.cell_porosity[a].min(fluid.cell_porosity[b]);
+            let permeability = fluid.cell_permeability[a].min(fluid.cell_permeability[b]);
+            1. / (1. + dt * viscosity * porosity / permeability)
+        };
+
+        for i in 1..self.f_num_x {
+            for j in 1..self.f_num_y {
+                let center = i * n + j;
+                let drag_u = drag(self, center, center - n);
+                let drag_v = drag(self, center, center - 1);
+                self.u[center] *= drag_u;
+                self.v[center] *= drag_v;
             }
         }
+    }
 
-        for component in 0..2 {
-            let dx = if component == 0 { 0.0 } else { h2 };
-            let dy = if component == 0 { h2 } else { 0.0 };
+    // Particles are never created or destroyed by freezing and melting, so mass is conserved.
+    fn update_phase(&mut self) {
+        let Some(freezing_point) = self.freezing_point else {
+            return;
+        };
 
-            let f = if component == 0 {
-                &mut self.u
-            } else {
-                &mut self.v
+        for i in 0..self.num_particles {
+            let temperature = self.particle_temperature[i];
+            if self.particle_frozen[i] {
+                self.particle_frozen[i] = temperature <= freezing_point + MELTING_HYSTERESIS;
+            } else if temperature < freezing_point
+                && !self.particle_ballistic[i]
+                && !self.particle_air[i]
+            {
+                self.particle_frozen[i] = true;
+                self.particle_vel[2 * i] = 0.;
+                self.particle_vel[2 * i + 1] = 0.;
+            }
+        }
+
+        let was_ice = std::mem::replace(&mut self.ice_cell, vec![false; self.f_num_cells]);
+        for (s, (&ice, &porosity)) in self
+            .s
+            .iter_mut()
+            .zip(was_ice.iter().zip(&self.cell_porosity))
+        {
+            if ice {
+                *s = porosity;
+            }
+        }
+
+        for i in 0..self.num_particles {
+            if !self.particle_frozen[i] {
+                continue;
+            }
+
+            let cell_nr = self.cell_index(self.position(i));
+            if self.s[cell_nr] != 0. {
+                self.ice_cell[cell_nr] = true;
+                self.s[cell_nr] = 0.;
+                if !was_ice[cell_nr] {
+                    self.stop_cell_faces(cell_nr);
+                }
+            }
+        }
+    }
+
+    // Solid faces keep their velocity through the particle to grid transfer, so a cell that
+    // just turned solid would otherwise keep pushing the liquid with its last flow velocity
+    fn stop_cell_faces(&mut self, cell_nr: usize) {
+        let n = self.f_num_y;
+        self.u[cell_nr] = 0.;
+        self.u[cell_nr + n] = 0.;
+        self.v[cell_nr] = 0.;
+        self.v[cell_nr + 1] = 0.;
+    }
+
+    fn particle_mass(&self, i: usize) -> f32 {
+        let density = if self.particle_air[i] {
+            self.air_density
+        } else {
+            1.
+        };
+        self.density * density * self.particle_area()
+    }
+
+    // Area each particle stands for when carrying sediment
+    fn particle_area(&self) -> f32 {
+        4. * self.particle_radius * self.particle_radius
+    }
+
+    fn column_index(&self, x: f32) -> usize {
+        ((x * self.f_inv_spacing).floor().max(0.) as usize).min(self.f_num_x - 1)
+    }
+
+    fn update_bed_cells(&mut self) {
+        let n = self.f_num_y;
+        let h = self.h;
+
+        let was_bed = std::mem::replace(&mut self.bed_cell, vec![false; self.f_num_cells]);
+        for (s, (&bed, &porosity)) in self
+            .s
+            .iter_mut()
+            .zip(was_bed.iter().zip(&self.cell_porosity))
+        {
+            if bed {
+                *s = porosity;
+            }
+        }
+
+        for i in 1..self.f_num_x - 1 {
+            for j in 1..self.f_num_y - 1 {
+                let cell_nr = i * n + j;
+                if (j as f32 + 0.5) * h >= h + self.bed_height[i] {
+                    break;
+                }
+                if self.s[cell_nr] != 0. {
+                    self.bed_cell[cell_nr] = true;
+                    self.s[cell_nr] = 0.;
+                    if !was_bed[cell_nr] {
+                        self.stop_cell_faces(cell_nr);
+                    }
+                }
+            }
+        }
+    }
+
+    // Lowest cell above the bed in a column
+    fn bed_surface_cell(&self, column: usize) -> usize {
+        let n = self.f_num_y;
+        let mut j = 1;
+        while j < n - 1 && self.bed_cell[column * n + j] {
+            j += 1;
+        }
+        column * n + j
+    }
+
+    fn transport_sediment(&mut self, dt: f32, sediment: Sediment) {
+        let n = self.f_num_y;
+        let h = self.h;
+        let particle_area = self.particle_area();
+
+        // Particles in the cell right above the bed exchange sediment with it
+        let mut num_surface_particles = vec![0; self.f_num_x];
+        let mut min_free = vec![MAX_SEDIMENT_CONCENTRATION; self.f_num_x];
+        for i in 0..self.num_particles {
+            if self.particle_air[i] || self.particle_ballistic[i] || self.is_immobile(i) {
+                continue;
+            }
+
+            let column = self.column_index(self.particle_pos[2 * i]);
+            if self.cell_index(self.position(i)) != self.bed_surface_cell(column) {
+                continue;
+            }
+
+            num_surface_particles[column] += 1;
+            min_free[column] =
+                min_free[column].min(MAX_SEDIMENT_CONCENTRATION - self.particle_sediment[i]);
+
+            // Deposition
+            let deposit = self.particle_sediment[i] * (sediment.settling_rate * dt).min(1.);
+            self.particle_sediment[i] -= deposit;
+            self.bed_height[column] += deposit * particle_area / h;
+        }
+
+        // Erosion from the shear rate of the flow over the bed, no slip at the bed surface
+        let mut picked_up = vec![f32::default(); self.f_num_x];
+        for column in 1..self.f_num_x - 1 {
+            let cell_nr = self.bed_surface_cell(column);
+            if num_surface_particles[column] == 0 || self.cell_type[cell_nr] != FLUID_CELL {
+                continue;
+            }
+
+            let tangential = 0.5 * (self.u[cell_nr] + self.u[cell_nr + n]);
+            let shear = tangential.abs() / (0.5 * h);
+            if shear <= sediment.critical_shear {
+                continue;
+            }
+
+            let capacity = num_surface_particles[column] as f32 * min_free[column] * particle_area;
+            let eroded = (sediment.erosion_rate * (shear - sediment.critical_shear) * dt * h)
+                .min(capacity)
+                .min(self.bed_height[column] * h);
+
+            self.bed_height[column] -= eroded / h;
+            picked_up[column] = eroded / (num_surface_particles[column] as f32 * particle_area);
+        }
+
+        for i in 0..self.num_particles {
+            if self.particle_air[i] || self.particle_ballistic[i] || self.is_immobile(i) {
+                continue;
+            }
+
+            let column = self.column_index(self.particle_pos[2 * i]);
+            if picked_up[column] > 0.
+                && self.cell_index(self.position(i)) == self.bed_surface_cell(column)
+            {
+                self.particle_sediment[i] += picked_up[column];
+            }
+        }
+
+        self.update_bed_cells();
+    }
+
+    fn apply_wetting(&mut self, dt: f32) {
+        let h = self.h;
+        let h1 = self.f_inv_spacing;
+        let n = self.f_num_y;
+
+        for i in 0..self.num_particles {
+            if self.particle_air[i] || self.particle_ballistic[i] || self.is_immobile(i) {
+                continue;
+            }
+
+            let pos = self.position(i);
+            let mut vel = self.velocity(i);
+            let surface_normal = self.surface_normal(pos);
+
+            // Distance to, inward normal and wetting of the nearby solid surfaces
+            let mut surfaces = Vec::with_capacity(2);
+
+            // Wetted solid cells around the particle. The nearest one gives the distance and
+            // wetting, the normal is blended over all in reach so stair steps read as a slope.
+            let xi = (pos.x * h1).floor() as i32;
+            let yi = (pos.y * h1).floor() as i32;
+            let mut nearest: Option<(f32, Wetting)> = None;
+            let mut normal = Vec2::ZERO;
+            for (ci, cj) in
+                (xi - 1..=xi + 1).flat_map(|ci| (yi - 1..=yi + 1).map(move |cj| (ci, cj)))
+            {
+                if ci < 0 || cj < 0 || ci as usize >= self.f_num_x || cj as usize >= n {
+                    continue;
+                }
+                let Some(wetting) = self.wetting_at(ci as usize, cj as usize) else {
+                    continue;
+                };
+                let min = Vec2::new(ci as f32, cj as f32) * h;
+                let offset = pos - pos.clamp(min, min + h);
+                let dist = offset.length();
+                // Inside porous cells there is no surface to wet
+                if dist == 0. || dist > h {
+                    continue;
+                }
+
+                normal += offset / dist * (1. - dist / h);
+                if nearest.is_none_or(|(nearest_dist, _)| dist < nearest_dist) {
+                    nearest = Some((dist, wetting));
+                }
+            }
+            if let Some((dist, wetting)) = nearest {
+                surfaces.push((dist, normal.normalize_or_zero(), wetting));
+            }
+
+            // Top of the sediment bed, where there is one
+            let column = self.column_index(pos.x);
+            if let Some(wetting) = self.sediment.and_then(|sediment| sediment.wetting) {
+                if self.bed_height[column] > 0. {
+                    let dist = pos.y - h - self.bed_height[column];
+                    surfaces.push((dist, Vec2::Y, wetting));
+                }
+            }
+
+            for (dist, normal, wetting) in surfaces {
+                if dist > h {
+                    continue;
+                }
+
+                let falloff = 1. - dist.max(0.) / h;
+                vel -= normal * wetting.adhesion * falloff * dt;
+
+                // Pushes the contact line along the solid until the surface meets it at the
+                // static contact angle
+                if let Some(surface_normal) = surface_normal {
+                    let tangent =
+                        (surface_normal - surface_normal.dot(normal) * normal).normalize_or_zero();
+                    let cos_angle = surface_normal.dot(normal);
+                    vel += tangent
+                        * CONTACT_LINE_STIFFNESS
+                        * (wetting.contact_angle.cos() - cos_angle)
+                        * falloff
+                        * dt;
+                }
+            }
+
+            self.particle_vel[2 * i] = vel.x;
+            self.particle_vel[2 * i + 1] = vel.y;
+        }
+    }
+
+    // Upper right corner of the box the particles are kept in
+    fn particle_max(&self) -> Vec2 {
+        let r = self.particle_radius;
+        Vec2::new(
+            (self.f_num_x - 1) as f32 * self.h - r * 4.,
+            (self.f_num_y - 1) as f32 * self.h - r * 4.,
+        )
+    }
+
+    fn particle_max_y(&self, x: f32) -> f32 {
+        bottle_neck_y(self.particle_max(), x)
+    }
+
+    // Only checks the first num_particles, which must be sorted into the particle cells
+    fn has_particle_within(&self, pos: Vec2, dist: f32, num_particles: usize) -> bool {
+        let pxi = (pos.x * self.p_inv_spacing).floor() as i32;
+        let pyi = (pos.y * self.p_inv_spacing).floor() as i32;
+        let x0 = (pxi - 1).max(0) as usize;
+        let y0 = (pyi - 1).max(0) as usize;
+        let x1 = ((pxi + 1) as usize).min(self.p_num_x - 1);
+        let y1 = ((pyi + 1) as usize).min(self.p_num_y - 1);
+
+        for xi in x0..=x1 {
+            for yi in y0..=y1 {
+                let cell_nr = xi * self.p_num_y + yi;
+                let first = self.first_cell_particle[cell_nr];
+                let last = self.first_cell_particle[cell_nr + 1];
+                for j in first..last {
+                    let id = self.cell_particle_ids[j];
+                    if id < num_particles && self.position(id).distance(pos) < dist {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    fn handle_particle_collision(&mut self) {
+        let h = 1. / self.f_inv_spacing;
+        let r = self.particle_radius;
+        let min_x = h + r;
+        let max_x = self.particle_max().x;
+        let min_y = h + r;
+
+        for i in 0..self.num_particles {
+            let mut x = self.particle_pos[2 * i];
+            let mut y = self.particle_pos[2 * i + 1];
+
+            let capped_y = self.particle_max_y(x);
+            let min_y = min_y + self.bed_height[self.column_index(x)];
+            let vel = self.velocity(i);
+            let mass = self.particle_mass(i);
+
+            // wall collisions
+            if x < min_x {
+                x = min_x;
+                self.particle_vel[2 * i] = 0.;
+                self.add_collision_impulse(
+                    i,
+                    Solid::Wall(Wall::Left),
+                    Vec2::new(x, y),
+                    Vec2::X * vel.x * mass,
+                );
+            } else if x > max_x {
+                x = max_x;
+                self.particle_vel[2 * i] = 0.;
+                self.add_collision_impulse(
+                    i,
+                    Solid::Wall(Wall::Right),
+                    Vec2::new(x, y),
+                    Vec2::X * vel.x * mass,
+                );
+            }
+
+            if y < min_y {
+                y = min_y;
+                self.particle_vel[2 * i + 1] = 0.;
+                self.add_collision_impulse(
+                    i,
+                    Solid::Wall(Wall::Bottom),
+                    Vec2::new(x, y),
+                    Vec2::Y * vel.y * mass,
+                );
+            } else if y > capped_y {
+                y = capped_y;
+                self.particle_vel[2 * i + 1] = 0.;
+                self.add_collision_impulse(
+                    i,
+                    Solid::Wall(Wall::Top),
+                    Vec2::new(x, y),
+                    Vec2::Y * vel.y * mass,
+                );
+            }
+
+            self.particle_pos[2 * i] = x;
+            self.particle_pos[2 * i + 1] = y;
+        }
+
+        if !self.obstacles.is_empty() {
+            self.collide_with_obstacles();
+        }
+
+        if let Some(tank) = self.moving_tank {
+            self.collide_with_moving_tank(tank);
+        }
+    }
+
+    // Pushes particles out of the obstacles through the nearest side
+    fn collide_with_obstacles(&mut self) {
+        let r = self.particle_radius;
+
+        for i in 0..self.num_particles {
+            for k in 0..self.obstacles.len() {
+                let min = self.obstacles[k].min - r;
+                let max = self.obstacles[k].max + r;
+                let pos = self.position(i);
+                if pos.cmple(min).any() || pos.cmpge(max).any() {
+                    continue;
+                }
+
+                // Penetration, outward normal and the point on each side
+                let (_, normal, pos) = [
+                    (pos.x - min.x, Vec2::NEG_X, Vec2::new(min.x, pos.y)),
+                    (max.x - pos.x, Vec2::X, Vec2::new(max.x, pos.y)),
+                    (pos.y - min.y, Vec2::NEG_Y, Vec2::new(pos.x, min.y)),
+                    (max.y - pos.y, Vec2::Y, Vec2::new(pos.x, max.y)),
+                ]
+                .into_iter()
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .unwrap();
+
+                let vel = self.velocity(i);
+                let inward = normal * vel.dot(normal).min(0.);
+                self.add_collision_impulse(
+                    i,
+                    Solid::Obstacle(k),
+                    pos,
+                    inward * self.particle_mass(i),
+                );
+                self.set_particle(i, pos, vel - inward);
+            }
+        }
+    }
+
+    // Keeps the particles inside the tank interior, including the bottle neck. The velocity
+    // along a wall normal is replaced by the velocity of the wall.
+    fn collide_with_moving_tank(&mut self, tank: MovingTank) {
+        let r = self.particle_radius;
+        let min = Vec2::splat(tank.wall_thickness + r);
+        let max = tank.size - tank.wall_thickness - r * 4.;
+
+        for i in 0..self.num_particles {
+            let pos = self.position(i);
+            let mut local = tank.to_local(pos);
+            let capped_y = bottle_neck_y(max, local.x);
+            let mut normals = Vec::new();
+
+            if local.x < min.x {
+                local.x = min.x;
+                normals.push((Wall::Left, Vec2::X));
+            } else if local.x > max.x {
+                local.x = max.x;
+                normals.push((Wall::Right, Vec2::NEG_X));
+            }
+
+            if local.y < min.y {
+                local.y = min.y;
+                normals.push((Wall::Bottom, Vec2::Y));
+            } else if local.y > capped_y {
+                local.y = capped_y;
+                normals.push((Wall::Top, Vec2::NEG_Y));
+            }
+
+            if normals.is_empty() {
+                continue;
+            }
+
+            let pos = tank.to_world(local);
+            let wall_velocity = tank.velocity_at(pos);
+            let mass = self.particle_mass(i);
+            let mut vel = self.velocity(i);
+            for (wall, normal) in normals {
+                let normal = tank.direction_to_world(normal);
+                let change = normal * (wall_velocity - vel).dot(normal);
+                self.add_collision_impulse(i, Solid::Wall(wall), pos, -change * mass);
+                vel += change;
+            }
+
+            self.set_particle(i, pos, vel);
+        }
+    }
+
+    fn update_tank_cells(&mut self) {
+        let Some(tank) = self.moving_tank else {
+            return;
+        };
+        let n = self.f_num_y;
+        let h = self.h;
+
+        for i in 0..self.f_num_cells {
+            if self.tank_cell[i] {
+                self.tank_cell[i] = false;
+                self.s[i] = self.cell_porosity[i];
+            }
+        }
+
+        for i in 0..self.f_num_x {
+            for j in 0..self.f_num_y {
+                let cell_nr = i * n + j;
+                let center = (Vec2::new(i as f32, j as f32) + 0.5) * h;
+                if !tank.is_inside(center) && self.s[cell_nr] != 0. {
+                    self.tank_cell[cell_nr] = true;
+                    self.s[cell_nr] = 0.;
+                }
+            }
+        }
+    }
+
+    // Faces touching a solid cell move with the tank, including those of the grid border
+    fn apply_tank_velocities(&mut self, tank: MovingTank) {
+        let n = self.f_num_y;
+        let h = self.h;
+
+        for i in 0..self.f_num_x {
+            for j in 0..self.f_num_y {
+                let solid = self.cell_type[i * n + j] == SOLID_CELL;
+                if solid || (i > 0 && self.cell_type[(i - 1) * n + j] == SOLID_CELL) {
+                    let face = Vec2::new(i as f32, j as f32 + 0.5) * h;
+                    self.u[i * n + j] = tank.velocity_at(face).x;
+                }
+                if solid || (j > 0 && self.cell_type[i * n + j - 1] == SOLID_CELL) {
+                    let face = Vec2::new(i as f32 + 0.5, j as f32) * h;
+                    self.v[i * n + j] = tank.velocity_at(face).y;
+                }
+            }
+        }
+    }
+
+    fn transfer_velocities(&mut self, flip_ratio: Option<f32>) {
+        let to_grid = flip_ratio.is_none();
+
+        let n = self.f_num_y;
+        let h = self.h;
+        let h1 = self.f_inv_spacing;
+        let h2 = 0.5 * h;
+
+        if to_grid {
+            self.prev_u = self.u.clone();
+            self.prev_v = self.v.clone();
+
+            self.du.fill(0.);
+            self.dv.fill(0.);
+            self.u.fill(0.);
+            self.v.fill(0.);
+
+            for i in 0..self.f_num_cells {
+                self.cell_type[i] = if self.s[i] == 0.0 {
+                    SOLID_CELL
+                } else {
+                    AIR_CELL
+                };
+            }
+            self.cell_air.fill(false);
+
+            for i in 0..self.num_particles {
+                if self.particle_ballistic[i] || self.is_immobile(i) {
+                    continue;
+                }
+
+                let x = self.particle_pos[2 * i];
+                let y = self.particle_pos[2 * i + 1];
+                let xi = ((x * h1).floor() as usize).clamp(0, self.f_num_x - 1);
+                let yi = ((y * h1).floor() as usize).clamp(0, self.f_num_y - 1);
+                let cell_nr = xi * n + yi;
+                if self.cell_type[cell_nr] == AIR_CELL {
+                    self.cell_type[cell_nr] = FLUID_CELL;
+                    self.cell_air[cell_nr] = self.particle_air[i];
+                } else if !self.particle_air[i] {
+                    // Liquid wins over air in mixed cells
+                    self.cell_air[cell_nr] = false;
+                }
+            }
+        }
+
+        for component in 0..2 {
+            let dx = if component == 0 { 0.0 } else { h2 };
+            let dy = if component == 0 { h2 } else { 0.0 };
+
+            let f = if component == 0 {
+                &mut self.u
+            } else {
+                &mut self.v
             };
             let prev_f = if component == 0 {
                 &self.prev_u
@@ -487,6 +2328,13 @@ impl FlipFluid {
             };
 
             for i in 0..self.num_particles {
+                if self.particle_ballistic[i]
+                    || self.particle_frozen[i]
+                    || self.particle_absorbed[i]
+                {
+                    continue;
+                }
+
                 let mut x = self.particle_pos[2 * i];
                 let mut y = self.particle_pos[2 * i + 1];
 
@@ -527,8 +2375,15 @@ impl FlipFluid {
                 } else {
                     let offset = if component == 0 { n } else { 1 };
 
+                    let valid = if component == 0 {
+                        &self.valid_u
+                    } else {
+                        &self.valid_v
+                    };
+
                     let valid0 = if self.cell_type[nr0] != AIR_CELL
                         || self.cell_type[nr0 - offset] != AIR_CELL
+                        || valid[nr0]
                     {
                         1.0
                     } else {
@@ -536,6 +2391,7 @@ impl FlipFluid {
                     };
                     let valid1 = if self.cell_type[nr1] != AIR_CELL
                         || self.cell_type[nr1 - offset] != AIR_CELL
+                        || valid[nr1]
                     {
                         1.0
                     } else {
@@ -543,6 +2399,7 @@ impl FlipFluid {
                     };
                     let valid2 = if self.cell_type[nr2] != AIR_CELL
                         || self.cell_type[nr2 - offset] != AIR_CELL
+                        || valid[nr2]
                     {
                         1.0
                     } else {
@@ -550,6 +2407,7 @@ impl FlipFluid {
                     };
                     let valid3 = if self.cell_type[nr3] != AIR_CELL
                         || self.cell_type[nr3 - offset] != AIR_CELL
+                        || valid[nr3]
                     {
                         1.0
                     } else {
@@ -602,16 +2460,107 @@ impl FlipFluid {
         }
     }
 
-    fn update_particle_density(&mut self) {
+    // Fills faces around the fluid layer by layer with the average of their valid neighbours,
+    // so that particles near the surface don't sample velocities that were never set.
+    fn extrapolate_velocities(&mut self) {
         let n = self.f_num_y;
-        let h = self.h;
-        let h1 = self.f_inv_spacing;
-        let h2 = 0.5 * h;
 
-        let d = &mut self.particle_density;
+        for component in 0..2 {
+            let offset = if component == 0 { n } else { 1 };
+            let (f, valid) = if component == 0 {
+                (&mut self.u, &mut self.valid_u)
+            } else {
+                (&mut self.v, &mut self.valid_v)
+            };
+
+            valid.fill(false);
+
+            for i in 0..self.f_num_x {
+                for j in 0..self.f_num_y {
+                    let nr = i * n + j;
+                    if (component == 0 && i == 0) || (component == 1 && j == 0) {
+                        continue;
+                    }
+
+                    let cell0 = self.cell_type[nr - offset];
+                    let cell1 = self.cell_type[nr];
+                    valid[nr] = (cell0 == FLUID_CELL || cell1 == FLUID_CELL)
+                        && cell0 != SOLID_CELL
+                        && cell1 != SOLID_CELL;
+                }
+            }
+
+            let mut layer = Vec::new();
+
+            for _ in 0..EXTRAPOLATION_LAYERS {
+                layer.clear();
+
+                for i in 0..self.f_num_x {
+                    for j in 0..self.f_num_y {
+                        let nr = i * n + j;
+                        if valid[nr] {
+                            continue;
+                        }
+
+                        // Faces touching solids keep their boundary velocities
+                        if self.cell_type[nr] == SOLID_CELL
+                            || (nr >= offset && self.cell_type[nr - offset] == SOLID_CELL)
+                        {
+                            continue;
+                        }
+
+                        let mut sum = 0.;
+                        let mut count = 0.;
+
+                        if i > 0 && valid[nr - n] {
+                            sum += f[nr - n];
+                            count += 1.;
+                        }
+                        if i < self.f_num_x - 1 && valid[nr + n] {
+                            sum += f[nr + n];
+                            count += 1.;
+                        }
+                        if j > 0 && valid[nr - 1] {
+                            sum += f[nr - 1];
+                            count += 1.;
+                        }
+                        if j < self.f_num_y - 1 && valid[nr + 1] {
+                            sum += f[nr + 1];
+                            count += 1.;
+                        }
+
+                        if count > 0. {
+                            layer.push((nr, sum / count));
+                        }
+                    }
+                }
+
+                for &(nr, value) in &layer {
+                    f[nr] = value;
+                    valid[nr] = true;
+                }
+            }
+        }
+    }
+
+    fn update_particle_density(&mut self) {
+        let n = self.f_num_y;
+        let h = self.h;
+        let h1 = self.f_inv_spacing;
+        let h2 = 0.5 * h;
+
+        let d = &mut self.particle_density;
         d.fill(0.);
 
         for i in 0..self.num_particles {
+            if self.particle_ballistic[i]
+                || self.particle_frozen[i]
+                || self.particle_absorbed[i]
+                || self.particle_air[i]
+            {
+                continue;
+            }
+
             let mut x = self.particle_pos[2 * i];
             let mut y = self.particle_pos[2 * i + 1];
 
@@ -648,7 +2597,7 @@ impl FlipFluid {
             let mut num_fluid_cells = 0.;
 
             for i in 0..self.f_num_cells {
-                if self.cell_type[i] == FLUID_CELL {
+                if self.cell_type[i] == FLUID_CELL && !self.cell_air[i] {
                     sum += d[i];
                     num_fluid_cells += 1.;
                 }
@@ -660,6 +2609,271 @@ impl FlipFluid {
         }
     }
 
+    // Re-injects the rotational energy lost to numerical dissipation, see
+    // "Visual Simulation of Smoke" (Fedkiw et al. 2001).
+    fn apply_vorticity_confinement(&mut self, dt: f32) {
+        let n = self.f_num_y;
+        let h = self.h;
+        let h1 = self.f_inv_spacing;
+
+        self.curl.fill(0.);
+
+        for i in 1..(self.f_num_x - 1) {
+            for j in 1..(self.f_num_y - 1) {
+                if self.cell_type[i * n + j] != FLUID_CELL {
+                    continue;
+                }
+
+                let center_v =
+                    |i: usize, j: usize| 0.5 * (self.v[i * n + j] + self.v[i * n + j + 1]);
+                let center_u =
+                    |i: usize, j: usize| 0.5 * (self.u[i * n + j] + self.u[(i + 1) * n + j]);
+
+                self.curl[i * n + j] = 0.5
+                    * h1
+                    * (center_v(i + 1, j) - center_v(i - 1, j) - center_u(i, j + 1)
+                        + center_u(i, j - 1));
+            }
+        }
+
+        let mut force_x = vec![0.; self.f_num_cells];
+        let mut force_y = vec![0.; self.f_num_cells];
+
+        for i in 1..(self.f_num_x - 1) {
+            for j in 1..(self.f_num_y - 1) {
+                let center = i * n + j;
+                if self.cell_type[center] != FLUID_CELL {
+                    continue;
+                }
+
+                // Gradient of the curl magnitude, one sided next to non fluid cells
+                let magnitude = |nr: usize| {
+                    if self.cell_type[nr] == FLUID_CELL {
+                        self.curl[nr].abs()
+                    } else {
+                        self.curl[center].abs()
+                    }
+                };
+
+                let eta_x = magnitude(center + n) - magnitude(center - n);
+                let eta_y = magnitude(center + 1) - magnitude(center - 1);
+                let eta_length = (eta_x * eta_x + eta_y * eta_y).sqrt();
+                if eta_length < f32::EPSILON {
+                    continue;
+                }
+
+                let nx = eta_x / eta_length;
+                let ny = eta_y / eta_length;
+                let w = self.curl[center];
+
+                force_x[center] = self.vorticity_confinement * h * ny * w;
+                force_y[center] = -self.vorticity_confinement * h * nx * w;
+            }
+        }
+
+        // Only faces between two fluid cells are accelerated
+        for i in 1..(self.f_num_x - 1) {
+            for j in 1..(self.f_num_y - 1) {
+                let center = i * n + j;
+                if self.cell_type[center] != FLUID_CELL {
+                    continue;
+                }
+
+                if self.cell_type[center - n] == FLUID_CELL {
+                    self.u[center] += dt * 0.5 * (force_x[center] + force_x[center - n]);
+                }
+                if self.cell_type[center - 1] == FLUID_CELL {
+                    self.v[center] += dt * 0.5 * (force_y[center] + force_y[center - 1]);
+                }
+            }
+        }
+    }
+
+    // Implicit viscosity step with the strain rate dependent viscosity of the rheology model.
+    // Solid faces act as no-slip walls. The viscosity is evaluated again from the solved
+    // velocities, otherwise the strain of this frame's acceleration alone yields material that
+    // should stay rigid.
+    fn apply_viscosity(&mut self, dt: f32, rheology: Rheology) {
+        let start_u = self.u.clone();
+        let start_v = self.v.clone();
+
+        for iteration in 0..RHEOLOGY_ITERS {
+            self.update_viscosity(rheology);
+            if iteration > 0 {
+                self.u.copy_from_slice(&start_u);
+                self.v.copy_from_slice(&start_v);
+            }
+            self.solve_viscosity(dt);
+        }
+    }
+
+    fn update_viscosity(&mut self, rheology: Rheology) {
+        let n = self.f_num_y;
+        let h1 = self.f_inv_spacing;
+
+        self.viscosity.fill(0.);
+
+        for i in 1..(self.f_num_x - 1) {
+            for j in 1..(self.f_num_y - 1) {
+                let center = i * n + j;
+                if self.cell_type[center] != FLUID_CELL {
+                    continue;
+                }
+
+                let center_u = |nr: usize| 0.5 * (self.u[nr] + self.u[nr + n]);
+                let center_v = |nr: usize| 0.5 * (self.v[nr] + self.v[nr + 1]);
+
+                let du_dx = (self.u[center + n] - self.u[center]) * h1;
+                let dv_dy = (self.v[center + 1] - self.v[center]) * h1;
+                let du_dy = (center_u(center + 1) - center_u(center - 1)) * 0.5 * h1;
+                let dv_dx = (center_v(center + n) - center_v(center - n)) * 0.5 * h1;
+                let shear = 0.5 * (du_dy + dv_dx);
+
+                let strain_rate =
+                    (2. * (du_dx * du_dx + dv_dy * dv_dy + 2. * shear * shear)).sqrt();
+                self.viscosity[center] = rheology.effective_viscosity(strain_rate);
+            }
+        }
+    }
+
+    fn solve_viscosity(&mut self, dt: f32) {
+        let n = self.f_num_y;
+        let h1 = self.f_inv_spacing;
+
+        let alpha = dt * h1 * h1;
+        let mut coefficients = vec![0.; self.u.len()];
+
+        for component in 0..2 {
+            let offset = if component == 0 { n } else { 1 };
+
+            coefficients.fill(0.);
+            for i in 1..(self.f_num_x - 1) {
+                for j in 1..(self.f_num_y - 1) {
+                    // Faces on the free surface take the viscosity of their fluid cell
+                    let center = i * n + j;
+                    let cells = [center, center - offset];
+                    let fluid = cells
+                        .iter()
+                        .filter(|&&nr| self.cell_type[nr] == FLUID_CELL)
+                        .count();
+                    if fluid > 0 && cells.iter().all(|&nr| self.cell_type[nr] != SOLID_CELL) {
+                        coefficients[center] = alpha
+                            * (self.viscosity[center] + self.viscosity[center - offset])
+                            / fluid as f32;
+                    }
+                }
+            }
+
+            // No-slip, the faces inside solids move with the wall instead of the
+            // extrapolated fluid velocity
+            let mut walls = vec![None; self.u.len()];
+            for i in 0..self.f_num_x {
+                for j in 0..self.f_num_y {
+                    let center = i * n + j;
+                    if (component == 0 && i == 0) || (component == 1 && j == 0) {
+                        continue;
+                    }
+                    if self.cell_type[center] != SOLID_CELL
+                        && self.cell_type[center - offset] != SOLID_CELL
+                    {
+                        continue;
+                    }
+
+                    walls[center] = Some(self.moving_tank.map_or(0., |tank| {
+                        let face = if component == 0 {
+                            Vec2::new(i as f32, j as f32 + 0.5)
+                        } else {
+                            Vec2::new(i as f32 + 0.5, j as f32)
+                        };
+                        tank.velocity_at(face * self.h)[component]
+                    }));
+                }
+            }
+
+            let f = if component == 0 {
+                &mut self.u
+            } else {
+                &mut self.v
+            };
+            solve_implicit_diffusion(f, &walls, &coefficients, n);
+        }
+    }
+
+    // Clamps the stress needed to stop all shearing to the Drucker-Prager yield surface,
+    // so that cells under enough pressure stay rigid and the others slide with friction.
+    fn apply_granular_friction(&mut self, dt: f32, angle_of_repose: f32) {
+        let n = self.f_num_y;
+        let h = self.h;
+        let h1 = self.f_inv_spacing;
+        let friction = angle_of_repose.sin();
+        let rigid_stiffness = self.density * h * h / dt;
+
+        let mut stress_xx = vec![0.; self.f_num_cells];
+        let mut stress_yy = vec![0.; self.f_num_cells];
+        let mut stress_xy = vec![0.; self.f_num_cells];
+
+        for i in 1..(self.f_num_x - 1) {
+            for j in 1..(self.f_num_y - 1) {
+                let center = i * n + j;
+                if self.cell_type[center] != FLUID_CELL {
+                    continue;
+                }
+
+                let center_u = |nr: usize| 0.5 * (self.u[nr] + self.u[nr + n]);
+                let center_v = |nr: usize| 0.5 * (self.v[nr] + self.v[nr + 1]);
+
+                let du_dx = (self.u[center + n] - self.u[center]) * h1;
+                let dv_dy = (self.v[center + 1] - self.v[center]) * h1;
+                let du_dy = (center_u(center + 1) - center_u(center - 1)) * 0.5 * h1;
+                let dv_dx = (center_v(center + n) - center_v(center - n)) * 0.5 * h1;
+                let trace = 0.5 * (du_dx + dv_dy);
+
+                let mut sxx = rigid_stiffness * (du_dx - trace);
+                let mut syy = rigid_stiffness * (dv_dy - trace);
+                let mut sxy = rigid_stiffness * 0.5 * (du_dy + dv_dx);
+
+                let shear_stress = (0.5 * (sxx * sxx + syy * syy) + sxy * sxy).sqrt();
+                let max_shear_stress = friction * self.p[center].max(0.);
+
+                if shear_stress > max_shear_stress {
+                    let scale = max_shear_stress / shear_stress;
+                    sxx *= scale;
+                    syy *= scale;
+                    sxy *= scale;
+                }
+
+                stress_xx[center] = sxx;
+                stress_yy[center] = syy;
+                stress_xy[center] = sxy;
+            }
+        }
+
+        let k = dt / self.density;
+
+        // The neighbours of the cells next to the border are solid border cells
+        for i in 1..(self.f_num_x - 1) {
+            for j in 1..(self.f_num_y - 1) {
+                let center = i * n + j;
+                if self.cell_type[center] != FLUID_CELL {
+                    continue;
+                }
+
+                if self.cell_type[center - n] == FLUID_CELL {
+                    let face_xy = |nr: usize| 0.5 * (stress_xy[nr] + stress_xy[nr - n]);
+                    self.u[center] += k
+                        * ((stress_xx[center] - stress_xx[center - n]) * h1
+                            + (face_xy(center + 1) - face_xy(center - 1)) * 0.5 * h1);
+                }
+                if self.cell_type[center - 1] == FLUID_CELL {
+                    let face_xy = |nr: usize| 0.5 * (stress_xy[nr] + stress_xy[nr - 1]);
+                    self.v[center] += k
+                        * ((stress_yy[center] - stress_yy[center - 1]) * h1
+                            + (face_xy(center + n) - face_xy(center - n)) * 0.5 * h1);
+                }
+            }
+        }
+    }
+
     fn solve_incompressibility(
         &mut self,
         num_iters: usize,
@@ -668,8 +2882,6 @@ impl FlipFluid {
         compensate_drift: bool,
     ) {
         self.p.fill(0.);
-        self.prev_u = self.u.clone();
-        self.prev_v = self.v.clone();
 
         let n = self.f_num_y;
         let cp = self.density * self.h / dt;
@@ -692,11 +2904,11 @@ impl FlipFluid {
                     let bottom = i * n + j - 1;
                     let top = i * n + j + 1;
 
-                    let s = self.s[center];
-                    let sx0 = self.s[left];
-                    let sx1 = self.s[right];
-                    let sy0 = self.s[bottom];
-                    let sy1 = self.s[top];
+                    // Faces are weighted by their inverse density, 1 for liquid
+                    let sx0 = self.s[left] * self.face_weight(center, left);
+                    let sx1 = self.s[right] * self.face_weight(center, right);
+                    let sy0 = self.s[bottom] * self.face_weight(center, bottom);
+                    let sy1 = self.s[top] * self.face_weight(center, top);
                     let s = sx0 + sx1 + sy0 + sy1;
                     if s == 0. {
                         continue;
@@ -711,24 +2923,215 @@ impl FlipFluid {
                         if compression > 0. {
                             div = div - k * compression;
                         }
+                    }
+
+                    let mut p = -div / s;
+                    p *= over_relaxation;
+
+                    // Granular material resists compression but is free to dilate
+                    if self.granular.is_some() && self.p[center] + cp * p < 0. {
+                        p = -self.p[center] / cp;
+                    }
+
+                    self.p[center] += cp * p;
+
+                    self.u[center] -= sx0 * p;
+                    self.u[right] += sx1 * p;
+                    self.v[center] -= sy0 * p;
+                    self.v[top] += sy1 * p;
+                }
+            }
+        }
+    }
+
+    fn simulate_gas(
+        &mut self,
+        dt: f32,
+        gas: Gas,
+        acceleration: Vec2,
+        num_pressure_iters: usize,
+        over_relaxation: f32,
+    ) {
+        self.emit_gas(dt);
+        self.apply_gas_buoyancy(dt, gas, acceleration);
+
+        for i in 0..self.f_num_cells {
+            self.cell_type[i] = if self.s[i] == 0.0 {
+                SOLID_CELL
+            } else {
+                FLUID_CELL
+            };
+        }
+
+        self.solve_incompressibility(num_pressure_iters, dt, over_relaxation, false);
+        self.advect_velocities(dt);
+        self.advect_gas(dt, gas);
+        self.update_cell_colors();
+    }
 
-                        let mut p = -div / s;
-                        p *= over_relaxation;
-                        self.p[center] += cp * p;
+    fn emit_gas(&mut self, dt: f32) {
+        let n = self.f_num_y;
+        let h = self.h;
 
-                        self.u[center] -= sx0 * p;
-                        self.u[right] += sx1 * p;
-                        self.v[center] -= sy0 * p;
-                        self.v[top] += sy1 * p;
+        for source in &self.gas_sources {
+            for i in 1..self.f_num_x - 1 {
+                for j in 1..self.f_num_y - 1 {
+                    let center = Vec2::new(i as f32 + 0.5, j as f32 + 0.5) * h;
+                    if center.distance(source.center) > source.radius {
+                        continue;
                     }
+
+                    let cell_nr = i * n + j;
+                    self.smoke_density[cell_nr] =
+                        (self.smoke_density[cell_nr] + source.rate * dt).min(1.);
+                    self.cell_temperature[cell_nr] = source.temperature;
+                }
+            }
+        }
+    }
+
+    // Uniform gravity is balanced by the pressure, only the density and temperature
+    // differences drive the flow.
+    fn apply_gas_buoyancy(&mut self, dt: f32, gas: Gas, acceleration: Vec2) {
+        let n = self.f_num_y;
+
+        let force = |density: f32, temperature: f32| {
+            gas.weight * density - gas.buoyancy * (temperature - AMBIENT_TEMPERATURE)
+        };
+
+        for i in 1..self.f_num_x {
+            for j in 1..self.f_num_y {
+                let center = i * n + j;
+                let left = (i - 1) * n + j;
+                let bottom = i * n + j - 1;
+
+                if self.s[center] != 0. && self.s[left] != 0. {
+                    let density = 0.5 * (self.smoke_density[center] + self.smoke_density[left]);
+                    let temperature =
+                        0.5 * (self.cell_temperature[center] + self.cell_temperature[left]);
+                    self.u[center] += dt * acceleration.x * force(density, temperature);
+                }
+
+                if self.s[center] != 0. && self.s[bottom] != 0. {
+                    let density = 0.5 * (self.smoke_density[center] + self.smoke_density[bottom]);
+                    let temperature =
+                        0.5 * (self.cell_temperature[center] + self.cell_temperature[bottom]);
+                    self.v[center] += dt * acceleration.y * force(density, temperature);
                 }
             }
         }
     }
 
+    // Semi-Lagrangian, traces each face back through the velocity field
+    fn advect_velocities(&mut self, dt: f32) {
+        let n = self.f_num_y;
+        let h = self.h;
+        let h2 = 0.5 * h;
+
+        let mut new_u = self.u.clone();
+        let mut new_v = self.v.clone();
+
+        for i in 1..self.f_num_x {
+            for j in 1..self.f_num_y {
+                let center = i * n + j;
+
+                if self.s[center] != 0. && self.s[(i - 1) * n + j] != 0. && j < self.f_num_y - 1 {
+                    let pos = Vec2::new(i as f32 * h, j as f32 * h + h2);
+                    let vel = self.sample_velocity(pos);
+                    new_u[center] = self.sample_velocity_component(pos - dt * vel, 0);
+                }
+
+                if self.s[center] != 0. && self.s[center - 1] != 0. && i < self.f_num_x - 1 {
+                    let pos = Vec2::new(i as f32 * h + h2, j as f32 * h);
+                    let vel = self.sample_velocity(pos);
+                    new_v[center] = self.sample_velocity_component(pos - dt * vel, 1);
+                }
+            }
+        }
+
+        self.u = new_u;
+        self.v = new_v;
+    }
+
+    fn advect_gas(&mut self, dt: f32, gas: Gas) {
+        let n = self.f_num_y;
+        let h = self.h;
+        let h2 = 0.5 * h;
+
+        let decay = 1. / (1. + gas.dissipation * dt);
+        let cooling = (gas.cooling * dt).min(1.);
+
+        let mut smoke_density = self.smoke_density.clone();
+        let mut cell_temperature = self.cell_temperature.clone();
+
+        for i in 1..self.f_num_x - 1 {
+            for j in 1..self.f_num_y - 1 {
+                let center = i * n + j;
+                if self.s[center] == 0. {
+                    continue;
+                }
+
+                let pos = Vec2::new(i as f32 * h + h2, j as f32 * h + h2);
+                let back = pos - dt * self.sample_velocity(pos);
+
+                smoke_density[center] =
+                    self.sample_field(&self.smoke_density, back, h2, h2) * decay;
+                let temperature = self.sample_field(&self.cell_temperature, back, h2, h2);
+                cell_temperature[center] =
+                    temperature + (AMBIENT_TEMPERATURE - temperature) * cooling;
+            }
+        }
+
+        self.smoke_density = smoke_density;
+        self.cell_temperature = cell_temperature;
+    }
+
+    // Grey smoke, glowing orange where it is hot
+    fn update_cell_colors(&mut self) {
+        for i in 0..self.f_num_cells {
+            let t = ((self.cell_temperature[i] - AMBIENT_TEMPERATURE) / 80.).clamp(0., 1.);
+            self.cell_color[3 * i] = 0.8 + 0.2 * t;
+            self.cell_color[3 * i + 1] = 0.8 - 0.3 * t;
+            self.cell_color[3 * i + 2] = 0.8 - 0.6 * t;
+        }
+    }
+
+    // Inverse of the density on the face between a fluid cell and its neighbour
+    fn face_weight(&self, center: usize, neighbour: usize) -> f32 {
+        let density = |cell: usize| {
+            if self.cell_air[cell] {
+                self.air_density
+            } else {
+                1.
+            }
+        };
+
+        if self.cell_type[neighbour] == FLUID_CELL {
+            2. / (density(center) + density(neighbour))
+        } else {
+            1. / density(center)
+        }
+    }
+
     fn update_particle_colors(&mut self) {
         let h1 = self.f_inv_spacing;
 
+        if self.color_mode == ColorMode::Temperature {
+            let (cold, hot) = self.wall_temperature.iter().flatten().fold(
+                (AMBIENT_TEMPERATURE, AMBIENT_TEMPERATURE),
+                |(min, max), t| (min.min(*t), max.max(*t)),
+            );
+
+            for i in 0..self.num_particles {
+                let t = ((self.particle_temperature[i] - cold) / (hot - cold).max(f32::EPSILON))
+                    .clamp(0., 1.);
+                self.particle_color[3 * i] = t;
+                self.particle_color[3 * i + 1] = 0.2;
+                self.particle_color[3 * i + 2] = 1. - t;
+            }
+            return;
+        }
+
         for i in 0..self.num_particles {
             let s = 0.01;
 
@@ -742,6 +3145,29 @@ impl FlipFluid {
             let yi = ((y * h1).floor() as usize).clamp(1, self.f_num_y - 1);
             let cell_nr = xi * self.f_num_y + yi;
 
+            if self.particle_frozen[i] {
+                self.particle_color[3 * i] = 0.8;
+                self.particle_color[3 * i + 1] = 0.95;
+                self.particle_color[3 * i + 2] = 1.0;
+                continue;
+            }
+
+            if self.particle_absorbed[i] {
+                self.particle_color[3 * i] = 0.1;
+                self.particle_color[3 * i + 1] = 0.2;
+                self.particle_color[3 * i + 2] = 0.6;
+                continue;
+            }
+
+            // Murky water
+            let murk = self.particle_sediment[i] / MAX_SEDIMENT_CONCENTRATION;
+            if murk > 0.05 {
+                self.particle_color[3 * i] = 0.2 + 0.5 * murk;
+                self.particle_color[3 * i + 1] = 0.3 + 0.3 * murk;
+                self.particle_color[3 * i + 2] = 1.0 - 0.6 * murk;
+                continue;
+            }
+
             let d0 = self.particle_rest_density;
 
             if d0 > 0.0 {
@@ -756,3 +3182,1103 @@ impl FlipFluid {
         }
     }
 }
+
+// The top of the tank is pointed like a bottle neck, max is the upper right corner for particles
+fn bottle_neck_y(max: Vec2, x: f32) -> f32 {
+    -((x - max.x * 0.5) * 0.5).abs() + max.y
+}
+
+// Implicit diffusion f - nu * laplacian(f) = f0 on the faces with a positive nu, other faces keep
+// their velocity. Wall neighbours hold the wall velocity, the others are a stress free surface
+// and drop out of the stencil. Divided by nu the system is symmetric positive definite, so
+// conjugate gradients converge even for the stiff viscosities of unyielded material.
+fn solve_implicit_diffusion(f: &mut [f32], walls: &[Option<f32>], nu: &[f32], n: usize) {
+    let neighbours = |center: usize| [center - n, center + n, center - 1, center + 1];
+    let active: Vec<usize> = (0..f.len()).filter(|&center| nu[center] > 0.).collect();
+    if active.is_empty() {
+        return;
+    }
+
+    let mut diagonal = vec![0.; f.len()];
+    for &center in &active {
+        let stencil = neighbours(center)
+            .into_iter()
+            .filter(|&nr| nu[nr] > 0. || walls[nr].is_some())
+            .count();
+        diagonal[center] = 1. / nu[center] + stencil as f32;
+    }
+
+    let apply = |x: &[f32], y: &mut [f32]| {
+        for &center in &active {
+            let coupled: f32 = neighbours(center)
+                .into_iter()
+                .filter(|&nr| nu[nr] > 0.)
+                .map(|nr| x[nr])
+                .sum();
+            y[center] = x[center] * diagonal[center] - coupled;
+        }
+    };
+    let dot = |a: &[f32], b: &[f32]| active.iter().map(|&i| a[i] * b[i]).sum::<f32>();
+
+    let mut b = vec![0.; f.len()];
+    for &center in &active {
+        let fixed: f32 = neighbours(center)
+            .into_iter()
+            .filter(|&nr| nu[nr] <= 0.)
+            .filter_map(|nr| walls[nr])
+            .sum();
+        b[center] = f[center] / nu[center] + fixed;
+    }
+
+    let mut x = f.to_vec();
+    let mut r = vec![0.; f.len()];
+    apply(&x, &mut r);
+    for &center in &active {
+        r[center] = b[center] - r[center];
+    }
+    let mut p = r.clone();
+    let mut ap = vec![0.; f.len()];
+    let mut r_r = dot(&r, &r);
+    let tolerance = VISCOSITY_TOLERANCE * VISCOSITY_TOLERANCE * dot(&b, &b);
+
+    for _ in 0..VISCOSITY_ITERS {
+        if r_r <= tolerance {
+            break;
+        }
+
+        apply(&p, &mut ap);
+        let step = r_r / dot(&p, &ap);
+        for &center in &active {
+            x[center] += step * p[center];
+            r[center] -= step * ap[center];
+        }
+
+        let next_r_r = dot(&r, &r);
+        let beta = next_r_r / r_r;
+        r_r = next_r_r;
+        for &center in &active {
+            p[center] = r[center] + beta * p[center];
+        }
+    }
+
+    for &center in &active {
+        f[center] = x[center];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isolated_particles_are_ballistic() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 17)
+            .with_solid_border()
+            .with_particles(4, 4)
+            .with_ballistic_droplets(2, 0.);
+
+        fluid.num_particles = 17;
+        fluid.particle_pos[32] = 20.;
+        fluid.particle_pos[33] = 40.;
+
+        fluid.update_particle_cells();
+        fluid.update_ballistic_particles();
+
+        assert!(fluid.particle_ballistic[0..16].iter().all(|b| !b));
+        assert!(fluid.particle_ballistic[16]);
+    }
+
+    #[test]
+    fn resting_pool_is_not_ballistic() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 900)
+            .with_solid_border()
+            .with_particles(30, 30)
+            .with_ballistic_droplets(2, 0.2);
+
+        for _ in 0..120 {
+            step(&mut fluid, Vec2::new(0., -400.));
+        }
+
+        let num_ballistic = fluid.particle_ballistic.iter().filter(|b| **b).count();
+        assert!(num_ballistic < 20);
+    }
+
+    #[test]
+    fn vorticity_confinement_skips_faces_next_to_air() {
+        let mut fluid = FlipFluid::new(1000., 10., 10., 1., 0.2, 0)
+            .with_solid_border()
+            .with_vorticity_confinement(10.);
+        let n = fluid.f_num_y;
+
+        // A rotating patch of fluid cells surrounded by air
+        fluid.cell_type.fill(AIR_CELL);
+        for i in 3..8 {
+            for j in 3..8 {
+                fluid.cell_type[i * n + j] = FLUID_CELL;
+            }
+        }
+        for i in 0..fluid.f_num_x {
+            for j in 0..fluid.f_num_y {
+                let dx = i as f32 - 5.5;
+                let dy = j as f32 - 5.5;
+                let falloff = (-(dx * dx + dy * dy) / 4.).exp();
+                fluid.u[i * n + j] = -dy * falloff;
+                fluid.v[i * n + j] = dx * falloff;
+            }
+        }
+        let u = fluid.u.clone();
+        let v = fluid.v.clone();
+
+        fluid.apply_vorticity_confinement(0.1);
+
+        assert!(fluid.curl[5 * n + 5] > 0.);
+        assert_eq!(fluid.u[3 * n + 5], u[3 * n + 5]);
+        assert_eq!(fluid.v[5 * n + 3], v[5 * n + 3]);
+        assert_eq!(fluid.u[8 * n + 5], u[8 * n + 5]);
+        assert_ne!(fluid.u[4 * n + 4], u[4 * n + 4]);
+    }
+
+    #[test]
+    fn velocities_are_extrapolated_into_air() {
+        let mut fluid = FlipFluid::new(1000., 10., 10., 1., 0.2, 0).with_solid_border();
+        let n = fluid.f_num_y;
+
+        fluid.cell_type.fill(AIR_CELL);
+        for i in 0..fluid.f_num_x {
+            for j in 0..fluid.f_num_y {
+                if fluid.s[i * n + j] == 0. {
+                    fluid.cell_type[i * n + j] = SOLID_CELL;
+                } else if j < 4 {
+                    fluid.cell_type[i * n + j] = FLUID_CELL;
+                }
+            }
+        }
+        fluid.u.fill(0.);
+        for i in 2..fluid.f_num_x - 1 {
+            for j in 1..4 {
+                fluid.u[i * n + j] = 3.;
+            }
+        }
+
+        fluid.extrapolate_velocities();
+
+        assert_eq!(fluid.u[5 * n + 4], 3.);
+        assert_eq!(fluid.u[5 * n + 5], 3.);
+        assert_eq!(fluid.u[5 * n + 6], 0.);
+        assert!(!fluid.valid_u[5 * n + 6]);
+    }
+
+    #[test]
+    fn runge_kutta_advection_follows_rotation() {
+        let mut fluid =
+            FlipFluid::new(1000., 20., 20., 1., 0.2, 1).with_advection(Advection::RungeKutta3);
+        let n = fluid.f_num_y;
+        let h = fluid.h;
+        let c = 10.;
+
+        for i in 0..fluid.f_num_x {
+            for j in 0..fluid.f_num_y {
+                fluid.u[i * n + j] = -((j as f32 + 0.5) * h - c);
+                fluid.v[i * n + j] = (i as f32 + 0.5) * h - c;
+            }
+        }
+
+        fluid.num_particles = 1;
+        fluid.particle_pos[0] = c + 4.;
+        fluid.particle_pos[1] = c;
+
+        for _ in 0..20 {
+            fluid.advect_particles(0.05);
+        }
+
+        let radius = fluid.position(0).distance(Vec2::splat(c));
+        assert!((radius - 4.).abs() < 0.001);
+        assert!(fluid.position(0).y > c);
+    }
+
+    #[test]
+    fn viscosity_damps_shear_flow() {
+        let mut fluid = FlipFluid::new(1000., 10., 10., 1., 0.2, 0).with_solid_border();
+        let n = fluid.f_num_y;
+
+        for i in 0..fluid.f_num_x {
+            for j in 0..fluid.f_num_y {
+                let solid = fluid.s[i * n + j] == 0.;
+                fluid.cell_type[i * n + j] = if solid { SOLID_CELL } else { FLUID_CELL };
+                fluid.u[i * n + j] = if solid { 0. } else { j as f32 * 0.1 };
+            }
+        }
+
+        fluid.apply_viscosity(0.01, Rheology::ketchup());
+
+        assert!(fluid.viscosity[5 * n + 5] > 1000.);
+        assert!(fluid.u[5 * n + 8].abs() < 0.1 * 8.);
+    }
+
+    // How far the material slides once the settled block is tilted
+    fn drift_in_tilted_tank(rheology: Rheology) -> f32 {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 120)
+            .with_solid_border()
+            .with_particles(15, 8)
+            .with_advection(Advection::RungeKutta3);
+        fluid.set_rheology(Some(rheology));
+        for _ in 0..60 {
+            step(&mut fluid, Vec2::new(0., -400.));
+        }
+        let start = center_of_mass(&fluid).x;
+
+        let gravity = Vec2::from_angle(0.4).rotate(Vec2::new(0., -400.));
+        for _ in 0..120 {
+            step(&mut fluid, gravity);
+        }
+
+        center_of_mass(&fluid).x - start
+    }
+
+    #[test]
+    fn yield_stress_holds_its_shape_in_a_tilted_tank() {
+        let runny = Rheology::Bingham {
+            yield_stress: 100.,
+            plastic_viscosity: 5.,
+        };
+
+        assert!(drift_in_tilted_tank(Rheology::water()) > 3.);
+        assert!(drift_in_tilted_tank(runny) > 5.);
+        assert!(drift_in_tilted_tank(Rheology::ketchup()) < 1.);
+    }
+
+    fn granular_block() -> FlipFluid {
+        let mut fluid = FlipFluid::new(1000., 10., 10., 1., 0.2, 0).with_solid_border();
+        fluid.set_granular(Some(30_f32.to_radians()));
+        let n = fluid.f_num_y;
+
+        for i in 0..fluid.f_num_x {
+            for j in 0..fluid.f_num_y {
+                let solid = fluid.s[i * n + j] == 0.;
+                fluid.cell_type[i * n + j] = if solid { SOLID_CELL } else { FLUID_CELL };
+            }
+        }
+        fluid.particle_rest_density = 1.;
+        fluid.particle_density.fill(1.);
+
+        fluid
+    }
+
+    #[test]
+    fn granular_pressure_does_not_resist_dilation() {
+        let mut fluid = granular_block();
+        let n = fluid.f_num_y;
+        fluid.u[5 * n + 5] = -1.;
+        fluid.u[6 * n + 5] = 1.;
+
+        fluid.solve_incompressibility(1, 0.01, 1.9, true);
+
+        assert!(fluid.p.iter().all(|p| *p >= 0.));
+        assert_eq!(fluid.p[5 * n + 5], 0.);
+        assert!(fluid.p[6 * n + 5] > 0.);
+    }
+
+    fn shear_rate(fluid: &FlipFluid) -> f32 {
+        let n = fluid.f_num_y;
+        (1..fluid.f_num_x - 1)
+            .flat_map(|i| (1..n - 2).map(move |j| i * n + j))
+            .map(|nr| (fluid.u[nr + 1] - fluid.u[nr]).powi(2))
+            .sum()
+    }
+
+    #[test]
+    fn granular_friction_needs_pressure() {
+        let mut fluid = granular_block();
+        let n = fluid.f_num_y;
+        // Shear band between a resting and a sliding layer
+        for i in 0..fluid.f_num_x {
+            for j in 5..fluid.f_num_y {
+                fluid.u[i * n + j] = 1.;
+            }
+        }
+        let u = fluid.u.clone();
+        let shear = shear_rate(&fluid);
+
+        fluid.apply_granular_friction(0.01, 30_f32.to_radians());
+        assert_eq!(fluid.u, u);
+
+        fluid.p.fill(1000000.);
+        fluid.apply_granular_friction(0.01, 30_f32.to_radians());
+        assert!(shear_rate(&fluid) < 0.9 * shear);
+    }
+
+    #[test]
+    fn granular_friction_reaches_the_floor() {
+        let mut fluid = granular_block();
+        let n = fluid.f_num_y;
+        // Sliding over a resting layer on the floor
+        for i in 0..fluid.f_num_x {
+            for j in 2..fluid.f_num_y {
+                fluid.u[i * n + j] = 1.;
+            }
+        }
+        fluid.p.fill(1000000.);
+
+        fluid.apply_granular_friction(0.01, 30_f32.to_radians());
+
+        // The resting layer is dragged along
+        assert!((2..fluid.f_num_x - 1).all(|i| fluid.u[i * n + 1] > 0.));
+    }
+
+    #[test]
+    fn heat_is_conducted_between_particles() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 16)
+            .with_particles(4, 4)
+            .with_heat_transfer(0.1, 0.);
+        fluid.particle_temperature[5] = 100.;
+
+        fluid.transfer_heat(0.01);
+
+        let total: f32 = fluid.particle_temperature[0..16].iter().sum();
+        assert!((total - (15. * AMBIENT_TEMPERATURE + 100.)).abs() < 0.01);
+        assert!(fluid.particle_temperature[5] < 100.);
+        assert!(fluid.particle_temperature[4] > AMBIENT_TEMPERATURE);
+    }
+
+    #[test]
+    fn air_does_not_conduct_heat() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 16)
+            .with_particles(4, 4)
+            .with_heat_transfer(0.1, 0.);
+        fluid.particle_temperature[5] = 100.;
+        fluid.particle_air[4] = true;
+        fluid.particle_air[6] = true;
+
+        fluid.transfer_heat(0.01);
+
+        assert_eq!(fluid.particle_temperature[4], AMBIENT_TEMPERATURE);
+        assert_eq!(fluid.particle_temperature[6], AMBIENT_TEMPERATURE);
+        let total: f32 = fluid.particle_temperature[0..16].iter().sum();
+        assert!((total - (15. * AMBIENT_TEMPERATURE + 100.)).abs() < 0.01);
+        assert!(fluid.particle_temperature[5] < 100.);
+    }
+
+    #[test]
+    fn heated_wall_warms_adjacent_particles() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 16)
+            .with_particles(4, 4)
+            .with_wall_temperature(Wall::Bottom, 80.);
+        fluid.particle_pos[31] = 20.;
+
+        fluid.transfer_heat(0.1);
+
+        assert!(fluid.particle_temperature[0] > AMBIENT_TEMPERATURE);
+        assert_eq!(fluid.particle_temperature[15], AMBIENT_TEMPERATURE);
+    }
+
+    #[test]
+    fn ice_cells_are_solid_until_melted() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 16)
+            .with_particles(4, 4)
+            .with_phase_change(0.)
+            .with_temperature_block(Vec2::ZERO, Vec2::splat(3.), -10.);
+        let frozen = fluid.particle_frozen.iter().filter(|f| **f).count();
+        let ice_cell = fluid.cell_index(fluid.position(0));
+
+        assert!(frozen > 0 && frozen < 16);
+        assert_eq!(fluid.s[ice_cell], 0.);
+
+        fluid.particle_temperature.fill(0.2);
+        fluid.update_phase();
+        assert_eq!(fluid.particle_frozen.iter().filter(|f| **f).count(), frozen);
+
+        fluid.particle_temperature.fill(1.);
+        fluid.update_phase();
+        assert!(fluid.particle_frozen.iter().all(|f| !f));
+        assert_eq!(fluid.s[ice_cell], 1.);
+        assert_eq!(fluid.num_particles(), 16);
+
+        // Freezing again stops the flow through the new ice
+        let n = fluid.f_num_y;
+        fluid.u.fill(1.);
+        fluid.v.fill(1.);
+        fluid.particle_temperature.fill(-10.);
+        fluid.update_phase();
+        assert_eq!(fluid.s[ice_cell], 0.);
+        assert_eq!(fluid.u[ice_cell], 0.);
+        assert_eq!(fluid.u[ice_cell + n], 0.);
+        assert_eq!(fluid.v[ice_cell], 0.);
+        assert_eq!(fluid.v[ice_cell + 1], 0.);
+    }
+
+    #[test]
+    fn hot_gas_rises() {
+        let mut fluid = FlipFluid::new(1000., 10., 10., 1., 0.2, 0).with_solid_border();
+        let n = fluid.f_num_y;
+        fluid.cell_temperature[5 * n + 5] = 100.;
+
+        fluid.apply_gas_buoyancy(0.1, Gas::steam(), Vec2::new(0., -100.));
+
+        assert!(fluid.v[5 * n + 5] > 0.);
+        assert!(fluid.v[5 * n + 6] > 0.);
+        assert_eq!(fluid.v[3 * n + 5], 0.);
+    }
+
+    #[test]
+    fn smoke_is_advected_with_the_flow() {
+        let mut fluid = FlipFluid::new(1000., 10., 10., 1., 0.2, 0).with_solid_border();
+        let n = fluid.f_num_y;
+        fluid.u.fill(1.);
+        fluid.smoke_density[4 * n + 5] = 1.;
+        let gas = Gas {
+            dissipation: 0.,
+            ..Gas::smoke()
+        };
+
+        fluid.advect_gas(0.5 * fluid.h, gas);
+
+        assert!((fluid.smoke_density[4 * n + 5] - 0.5).abs() < 0.0001);
+        assert!((fluid.smoke_density[5 * n + 5] - 0.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn air_particles_fill_the_tank_around_the_liquid() {
+        let fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 1000)
+            .with_solid_border()
+            .with_particles(4, 4)
+            .with_air_particles(0.1);
+
+        assert!(fluid.num_particles() > 16);
+        assert!((0..16).all(|i| !fluid.is_air(i)));
+        assert!((16..fluid.num_particles()).all(|i| fluid.is_air(i)));
+        for i in 16..fluid.num_particles() {
+            assert!(!fluid.has_particle_within(fluid.position(i), 1.9 * fluid.particle_radius, 16));
+        }
+    }
+
+    #[test]
+    fn air_particles_are_not_ballistic() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 3000)
+            .with_solid_border()
+            .with_particles(10, 10)
+            .with_ballistic_droplets(2, 0.2)
+            .with_air_particles(0.1);
+
+        for _ in 0..30 {
+            step(&mut fluid, Vec2::new(0., -400.));
+        }
+
+        let air_cells = (0..fluid.f_num_cells)
+            .filter(|&i| fluid.cell_type[i] == FLUID_CELL && fluid.cell_air[i])
+            .count();
+        let ballistic_air = (0..fluid.num_particles())
+            .filter(|&i| fluid.is_air(i) && fluid.particle_ballistic[i])
+            .count();
+        assert!(air_cells > 100);
+        assert_eq!(ballistic_air, 0);
+    }
+
+    #[test]
+    fn air_cells_are_lighter_in_the_pressure_solve() {
+        let mut fluid = FlipFluid::new(1000., 10., 10., 1., 0.2, 0).with_solid_border();
+        fluid.air_density = 0.1;
+        let n = fluid.f_num_y;
+        fluid.cell_type.fill(FLUID_CELL);
+        fluid.cell_air[5 * n + 6] = true;
+
+        assert_eq!(fluid.face_weight(5 * n + 4, 5 * n + 5), 1.);
+        assert!((fluid.face_weight(5 * n + 5, 5 * n + 6) - 2. / 1.1).abs() < 0.0001);
+        assert!((fluid.face_weight(5 * n + 6, 5 * n + 7) - 2. / 1.1).abs() < 0.0001);
+    }
+
+    fn sponge_tank(capacity: usize) -> FlipFluid {
+        FlipFluid::new(1000., 30., 50., 2., 0.2, 16)
+            .with_solid_border()
+            .with_particles(4, 4)
+            .with_sponge(Sponge {
+                min: Vec2::ZERO,
+                max: Vec2::new(30., 6.),
+                porosity: 0.5,
+                permeability: 0.00001,
+                capacity,
+                wetting: None,
+            })
+    }
+
+    #[test]
+    fn darcy_drag_slows_flow_in_sponges() {
+        let mut fluid = sponge_tank(0);
+        let n = fluid.f_num_y;
+        fluid.u.fill(1.);
+
+        fluid.apply_darcy_drag(0.01);
+
+        assert!(fluid.u[5 * n + 1] < 0.5);
+        assert_eq!(fluid.u[5 * n + 10], 1.);
+        assert_eq!(fluid.s[5 * n + 1], 0.5);
+    }
+
+    #[test]
+    fn sponges_absorb_up_to_capacity_and_release_when_squeezed() {
+        let mut fluid = sponge_tank(10);
+        let num_absorbed =
+            |fluid: &FlipFluid| fluid.particle_absorbed.iter().filter(|a| **a).count();
+
+        fluid.absorb_particles();
+        assert_eq!(num_absorbed(&fluid), 10);
+
+        fluid.set_squeeze(0.5);
+        fluid.absorb_particles();
+        assert_eq!(num_absorbed(&fluid), 5);
+        assert_eq!(fluid.num_particles(), 16);
+    }
+
+    fn sediment_tank() -> FlipFluid {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 16)
+            .with_solid_border()
+            .with_particles(4, 4)
+            .with_sediment_bed(3., Sediment::sand());
+        let n = fluid.f_num_y;
+        let h = fluid.h;
+        for i in 0..16 {
+            fluid.particle_pos[2 * i + 1] += 2. * h;
+        }
+        fluid.transfer_velocities(None);
+        assert!(fluid.bed_cell[n + 1] && fluid.bed_cell[2 * n + 1]);
+        assert!(!fluid.bed_cell[n + 3]);
+        fluid
+    }
+
+    fn total_sediment(fluid: &FlipFluid) -> f32 {
+        let carried: f32 = fluid.particle_sediment.iter().sum();
+        let bed: f32 = fluid.bed_height.iter().sum();
+        carried * fluid.particle_area() + bed * fluid.h
+    }
+
+    #[test]
+    fn shear_erodes_the_bed() {
+        let mut fluid = sediment_tank();
+        let total = total_sediment(&fluid);
+        fluid.u.fill(100.);
+
+        fluid.transport_sediment(0.1, Sediment::sand());
+
+        assert!(fluid.bed_height[1] < 3.);
+        assert!(fluid.particle_sediment.iter().any(|c| *c > 0.));
+        assert!((total_sediment(&fluid) - total).abs() < 0.001);
+    }
+
+    #[test]
+    fn sediment_settles_in_calm_water() {
+        let mut fluid = sediment_tank();
+        fluid.particle_sediment[0..16].fill(0.1);
+        let total = total_sediment(&fluid);
+
+        fluid.transport_sediment(0.1, Sediment::sand());
+
+        assert!(fluid.bed_height[1] > 3.);
+        assert!((total_sediment(&fluid) - total).abs() < 0.001);
+    }
+
+    #[test]
+    fn growing_bed_stops_the_flow_it_covers() {
+        let mut fluid = sediment_tank();
+        let n = fluid.f_num_y;
+        let covered = n + 3;
+        fluid.u.fill(1.);
+        fluid.v.fill(1.);
+        fluid.bed_height[1] = 7.;
+        assert!(!fluid.bed_cell[covered]);
+
+        fluid.update_bed_cells();
+
+        assert!(fluid.bed_cell[covered]);
+        assert_eq!(fluid.u[covered], 0.);
+        assert_eq!(fluid.u[covered + n], 0.);
+        assert_eq!(fluid.v[covered], 0.);
+        assert_eq!(fluid.v[covered + 1], 0.);
+        // Cells that were already bed keep their velocities
+        assert_eq!(fluid.v[2 * n + 1], 1.);
+    }
+
+    #[test]
+    fn adhesion_pulls_particles_to_the_wall() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 2)
+            .with_solid_border()
+            .with_particles(2, 1)
+            .with_wall_wetting(Wall::Left, Wetting::glass());
+        fluid.particle_pos[2] = 15.;
+
+        fluid.apply_wetting(0.1);
+
+        assert!(fluid.velocity(0).x < 0.);
+        assert_eq!(fluid.velocity(1), Vec2::ZERO);
+    }
+
+    #[test]
+    fn wetting_wall_lifts_the_contact_line() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 1)
+            .with_solid_border()
+            .with_particles(1, 1)
+            .with_wall_wetting(
+                Wall::Left,
+                Wetting {
+                    adhesion: 0.,
+                    ..Wetting::glass()
+                },
+            );
+        let n = fluid.f_num_y;
+        // Liquid in the two bottom rows, so the surface normal at the wall points up
+        fluid.particle_rest_density = 1.;
+        for i in 1..fluid.f_num_x - 1 {
+            fluid.particle_density[i * n + 1] = 1.;
+            fluid.particle_density[i * n + 2] = 1.;
+        }
+        fluid.particle_pos[0] = fluid.h * 1.2;
+        fluid.particle_pos[1] = fluid.h * 2.9;
+
+        fluid.apply_wetting(0.01);
+
+        assert!(fluid.velocity(0).y > 0.);
+    }
+
+    #[test]
+    fn adhesion_follows_the_slanted_neck() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 2)
+            .with_solid_border()
+            .with_bottle_neck()
+            .with_particles(2, 1)
+            .with_wall_wetting(Wall::Top, Wetting::glass());
+        let max = fluid.particle_max();
+        for (i, x) in [0.25 * max.x, 0.75 * max.x].into_iter().enumerate() {
+            fluid.particle_pos[2 * i] = x;
+            fluid.particle_pos[2 * i + 1] = fluid.particle_max_y(x) - 0.5;
+        }
+
+        fluid.apply_wetting(0.1);
+
+        // Pulled up the slope towards the cap, not straight up
+        assert!(fluid.velocity(0).x < 0. && fluid.velocity(0).y > 0.);
+        assert!(fluid.velocity(1).x > 0. && fluid.velocity(1).y > 0.);
+    }
+
+    #[test]
+    fn solids_carry_their_own_wetting() {
+        let wetted = Obstacle {
+            min: Vec2::new(10., 20.),
+            max: Vec2::new(14., 24.),
+            wetting: Some(Wetting::glass()),
+        };
+        let dry = Obstacle {
+            min: Vec2::new(20., 20.),
+            max: Vec2::new(24., 24.),
+            wetting: None,
+        };
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 3)
+            .with_solid_border()
+            .with_particles(3, 1)
+            .with_sediment_bed(3., Sediment::sand())
+            .with_obstacle(wetted)
+            .with_obstacle(dry);
+        let positions = [
+            Vec2::new(14.5, 22.),
+            Vec2::new(24.5, 22.),
+            Vec2::new(7., 5.5),
+        ];
+        for (i, pos) in positions.into_iter().enumerate() {
+            fluid.particle_pos[2 * i] = pos.x;
+            fluid.particle_pos[2 * i + 1] = pos.y;
+        }
+
+        fluid.apply_wetting(0.1);
+
+        assert!(fluid.velocity(0).x < 0.);
+        assert_eq!(fluid.velocity(1), Vec2::ZERO);
+        // Pulled onto the top of the bed, which is above the bed cells
+        assert!(fluid.velocity(2).y < 0.);
+    }
+
+    // Same liquid in the tank frame and in a world-frame grid, with the tank at the given pose
+    fn tank_and_world_frame(center: Vec2, angle: f32) -> (FlipFluid, FlipFluid) {
+        let tank_frame = FlipFluid::new(1000., 30., 50., 2., 0.2, 225)
+            .with_solid_border()
+            .with_particles(15, 15);
+        let (num_x, num_y) = tank_frame.grid_size();
+        let size = Vec2::new(num_x as f32, num_y as f32) * tank_frame.h;
+
+        let mut tank = MovingTank::new(size, tank_frame.h);
+        tank.center = center;
+        tank.angle = angle;
+        let world_frame = FlipFluid::new(1000., 80., 80., 2., 0.2, 225)
+            .with_solid_border()
+            .with_moving_tank(tank)
+            .with_particles(15, 15);
+
+        (tank_frame, world_frame)
+    }
+
+    fn center_of_mass(fluid: &FlipFluid) -> Vec2 {
+        (0..fluid.num_particles)
+            .map(|i| fluid.position(i))
+            .sum::<Vec2>()
+            / fluid.num_particles as f32
+    }
+
+    fn step(fluid: &mut FlipFluid, acceleration: Vec2) {
+        fluid.simulate(
+            1. / 60.,
+            acceleration.x,
+            acceleration.y,
+            0.,
+            0.,
+            0.,
+            0.,
+            0.9,
+            50,
+            2,
+            1.9,
+            true,
+            true,
+        );
+    }
+
+    #[test]
+    fn world_frame_matches_tank_frame_in_a_tilted_tank() {
+        let angle = 0.3;
+        let gravity = Vec2::new(0., -400.);
+        let (mut tank_frame, mut world_frame) = tank_and_world_frame(Vec2::splat(40.), angle);
+        let tank = world_frame.moving_tank().unwrap();
+
+        for _ in 0..90 {
+            step(&mut tank_frame, Vec2::from_angle(-angle).rotate(gravity));
+            step(&mut world_frame, gravity);
+        }
+
+        let expected = center_of_mass(&tank_frame);
+        let actual = tank.to_local(center_of_mass(&world_frame));
+        assert!(expected.distance(actual) < 1.);
+    }
+
+    #[test]
+    fn world_frame_matches_tank_frame_in_a_moving_tank() {
+        let velocity = Vec2::new(20., 10.);
+        let gravity = Vec2::new(0., -400.);
+        let (mut tank_frame, mut world_frame) = tank_and_world_frame(Vec2::splat(30.), 0.);
+
+        for frame in 1..=60 {
+            let center = Vec2::splat(30.) + velocity * frame as f32 / 60.;
+            world_frame.set_tank_motion(center, 0., velocity, 0.);
+            step(&mut tank_frame, gravity);
+            step(&mut world_frame, gravity);
+        }
+
+        let tank = world_frame.moving_tank().unwrap();
+        let expected = center_of_mass(&tank_frame);
+        let actual = tank.to_local(center_of_mass(&world_frame));
+        assert!(expected.distance(actual) < 1.);
+        assert!((0..world_frame.num_particles).all(|i| tank.is_inside(world_frame.position(i))));
+    }
+
+    #[test]
+    fn resting_liquid_weighs_on_the_walls() {
+        let gravity = Vec2::new(0., -400.);
+        let (mut fluid, _) = tank_and_world_frame(Vec2::ZERO, 0.);
+        let weight: Vec2 = (0..fluid.num_particles)
+            .map(|i| gravity * fluid.particle_mass(i))
+            .sum();
+
+        let mut force = Vec2::ZERO;
+        for frame in 0..120 {
+            step(&mut fluid, gravity);
+            if frame >= 60 {
+                force += fluid.tank_reaction(Vec2::ZERO, gravity, gravity, 0.).0 / 60.;
+            }
+        }
+
+        assert!(force.distance(weight) < 0.1 * weight.length());
+    }
+
+    #[test]
+    fn released_tank_falls_together_with_its_liquid() {
+        let gravity = Vec2::new(0., -400.);
+        let dt = 1. / 60.;
+        let (mut fluid, _) = tank_and_world_frame(Vec2::ZERO, 0.);
+        // Until the block has spread out and stopped sloshing
+        for _ in 0..300 {
+            step(&mut fluid, gravity);
+        }
+
+        // As heavy as the liquid, which is where a lagging coupling blows up
+        let center = fluid.size * 0.5;
+        let liquid = fluid.mass_properties(center);
+        let mut tank = BodyState {
+            mass: liquid.mass,
+            moment_of_inertia: liquid.moment_of_inertia,
+            center,
+            angle: 0.,
+            linear_velocity: Vec2::ZERO,
+            angular_velocity: 0.,
+        };
+        let mut tank_acceleration = Vec2::ZERO;
+        for _ in 0..30 {
+            let linear_acceleration = gravity - tank_acceleration;
+            step(&mut fluid, linear_acceleration);
+            let liquid = fluid.mass_properties(center);
+            let (force, torque) = fluid.tank_reaction(center, gravity, linear_acceleration, 0.);
+            let body = BodyState {
+                mass: tank.mass + liquid.mass,
+                moment_of_inertia: tank.moment_of_inertia + liquid.moment_of_inertia,
+                ..tank
+            };
+
+            let (linear_velocity, angular_velocity) =
+                integrate_rigid_body(body, None, force + gravity * tank.mass, torque, dt);
+            tank_acceleration = (linear_velocity - tank.linear_velocity) / dt;
+            tank.linear_velocity = linear_velocity;
+            tank.angular_velocity = angular_velocity;
+        }
+
+        let free_fall = gravity * 30. * dt;
+        assert!(tank.linear_velocity.distance(free_fall) < 0.02 * free_fall.length());
+        assert!(tank.angular_velocity.abs() < 0.01);
+        // The liquid floats at rest in the tank
+        let liquid = fluid.mass_properties(center);
+        assert!(liquid.linear_momentum.length() < 0.01 * free_fall.length() * liquid.mass);
+    }
+
+    #[test]
+    fn world_mass_properties_follow_the_tank() {
+        let (mut fluid, _) = tank_and_world_frame(Vec2::ZERO, 0.);
+        for i in 0..fluid.num_particles {
+            fluid.set_particle(i, fluid.position(i), Vec2::new(1., 2.));
+        }
+        let transform = GlobalTransform::from(
+            Transform::from_xyz(10., -5., 0.).with_rotation(Quat::from_rotation_z(0.5)),
+        );
+
+        let center = fluid.size * 0.5;
+        let local = fluid.mass_properties(center);
+        let world = fluid.world_mass_properties(Vec2::new(10., -5.), &transform, Vec2::ZERO, 0.);
+
+        let center_of_mass = transform
+            .transform_point((local.center_of_mass - center).extend(0.))
+            .xy();
+        assert_eq!(world.mass, local.mass);
+        assert!(world.center_of_mass.distance(center_of_mass) < 0.001);
+        assert!((world.angular_momentum - local.angular_momentum).abs() < 0.01 * local.mass);
+        assert!(
+            (world.moment_of_inertia - local.moment_of_inertia).abs()
+                < 0.001 * local.moment_of_inertia
+        );
+        assert!((world.kinetic_energy - local.kinetic_energy).abs() < 0.001 * local.kinetic_energy);
+        assert!(
+            world
+                .linear_momentum
+                .distance(Vec2::from_angle(0.5).rotate(local.linear_momentum))
+                < 0.01 * local.mass
+        );
+    }
+
+    #[test]
+    fn world_mass_properties_move_with_the_tank() {
+        let (mut fluid, _) = tank_and_world_frame(Vec2::ZERO, 0.);
+        for i in 0..fluid.num_particles {
+            fluid.set_particle(i, fluid.position(i), Vec2::ZERO);
+        }
+        let center = Vec2::new(10., -5.);
+        let transform = GlobalTransform::from(
+            Transform::from_translation(center.extend(0.))
+                .with_rotation(Quat::from_rotation_z(0.5)),
+        );
+        let (linear_velocity, angular_velocity) = (Vec2::new(3., -4.), 0.2);
+
+        let world =
+            fluid.world_mass_properties(center, &transform, linear_velocity, angular_velocity);
+
+        // Liquid at rest in the tank moves rigidly with it
+        let arm = world.center_of_mass - center;
+        let momentum = world.mass * (linear_velocity + arm.perp() * angular_velocity);
+        assert!(world.linear_momentum.distance(momentum) < 0.001 * momentum.length());
+        let angular_momentum =
+            arm.perp_dot(world.mass * linear_velocity) + world.moment_of_inertia * angular_velocity;
+        assert!((world.angular_momentum - angular_momentum).abs() < 0.001 * angular_momentum.abs());
+    }
+
+    fn average_loads(fluid: &mut FlipFluid, frames: usize) -> Vec<(Solid, Load)> {
+        let gravity = Vec2::new(0., -400.);
+        for _ in 0..frames {
+            step(fluid, gravity);
+        }
+
+        let mut loads = fluid.solid_loads().to_vec();
+        for _ in 1..frames {
+            step(fluid, gravity);
+            for (load, (_, new_load)) in loads.iter_mut().zip(fluid.solid_loads()) {
+                load.1.force += new_load.force;
+                load.1.torque += new_load.torque;
+            }
+        }
+        for (_, load) in &mut loads {
+            load.force /= frames as f32;
+            load.torque /= frames as f32;
+        }
+        loads
+    }
+
+    fn load_on(loads: &[(Solid, Load)], solid: Solid) -> Load {
+        loads.iter().find(|(s, _)| *s == solid).unwrap().1
+    }
+
+    #[test]
+    fn resting_liquid_loads_the_bottom_wall() {
+        let (mut fluid, _) = tank_and_world_frame(Vec2::ZERO, 0.);
+        let weight = fluid.mass_properties(Vec2::ZERO).mass * 400.;
+
+        let loads = average_loads(&mut fluid, 60);
+
+        let bottom = load_on(&loads, Solid::Wall(Wall::Bottom));
+        let left = load_on(&loads, Solid::Wall(Wall::Left));
+        let right = load_on(&loads, Solid::Wall(Wall::Right));
+        assert!((bottom.force.y + weight).abs() < 0.1 * weight);
+        assert!(left.force.x < 0.);
+        assert!(right.force.x > 0.);
+    }
+
+    #[test]
+    fn obstacles_carry_the_liquid_above_them() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 225)
+            .with_solid_border()
+            .with_obstacle(Obstacle {
+                min: Vec2::new(1., 6.),
+                max: Vec2::new(30., 10.),
+                wetting: None,
+            });
+        fluid.num_particles = 225;
+        for i in 0..225 {
+            let pos = Vec2::new(3. + (i % 15) as f32 * 0.4, 12. + (i / 15) as f32 * 0.4);
+            fluid.set_particle(i, pos, Vec2::ZERO);
+        }
+        let weight = fluid.mass_properties(Vec2::ZERO).mass * 400.;
+
+        let loads = average_loads(&mut fluid, 60);
+
+        let shelf = load_on(&loads, Solid::Obstacle(0));
+        let bottom = load_on(&loads, Solid::Wall(Wall::Bottom));
+        assert!((shelf.force.y + weight).abs() < 0.05 * weight);
+        assert!(bottom.force.length() < 0.01 * weight);
+    }
+
+    // Moves the body like integrate_position and integrate_rotation, pinned bodies turn
+    // around the pivot
+    fn step_body(body: &mut BodyState, mount: Option<Mount>, force: Vec2, dt: f32) {
+        (body.linear_velocity, body.angular_velocity) =
+            integrate_rigid_body(*body, mount, force, 0., dt);
+        let turn = body.angular_velocity * dt;
+        if let Some(pivot) = mount.and_then(|mount| mount.pivot()) {
+            body.center = pivot + Vec2::from_angle(turn).rotate(body.center - pivot);
+        } else {
+            body.center += body.linear_velocity * dt;
+        }
+        body.angle += turn;
+    }
+
+    fn body_at(center: Vec2) -> BodyState {
+        BodyState {
+            mass: 1.,
+            moment_of_inertia: 1.,
+            center,
+            angle: 0.,
+            linear_velocity: Vec2::ZERO,
+            angular_velocity: 0.,
+        }
+    }
+
+    #[test]
+    fn pendulum_swings_with_its_period() {
+        let pivot = Vec2::new(0., 60.);
+        let mount = Some(Mount::Pendulum {
+            pivot,
+            friction: 0.,
+        });
+        let mut body = body_at(pivot + Vec2::from_angle(0.1).rotate(Vec2::new(0., -60.)));
+        body.moment_of_inertia = 0.;
+        let weight = Vec2::new(0., -400.);
+        let dt = 1. / 600.;
+
+        let mut crossings = vec![];
+        let mut side = body.center.x > pivot.x;
+        for frame in 0..3000 {
+            step_body(&mut body, mount, weight, dt);
+            if (body.center.x > pivot.x) != side {
+                side = !side;
+                crossings.push(frame as f32 * dt);
+            }
+        }
+
+        let period = 2. * PI * (60_f32 / 400.).sqrt();
+        assert!(crossings.len() >= 3);
+        assert!((crossings[2] - crossings[0] - period).abs() < 0.01 * period);
+    }
+
+    #[test]
+    fn hinge_stops_at_its_limits_across_the_wrap() {
+        // Upside down, the range crosses the angle where to_euler wraps from PI to -PI
+        let mount = Some(Mount::Hinge {
+            pivot: Vec2::ZERO,
+            min_angle: PI - 0.8,
+            max_angle: PI + 0.8,
+        });
+        let mut body = body_at(Vec2::new(10., 0.));
+        let push = |body: BodyState, torque: f32| {
+            integrate_rigid_body(body, mount, Vec2::ZERO, torque, 1. / 60.).1
+        };
+
+        body.angle = -3.;
+        assert!(push(body, -1.) < 0.);
+        assert!(push(body, 1.) > 0.);
+
+        body.angle = -(PI - 0.81);
+        assert_eq!(push(body, 1.), 0.);
+        assert!(push(body, -1.) < 0.);
+
+        body.angle = PI - 0.81;
+        assert_eq!(push(body, -1.), 0.);
+        assert!(push(body, 1.) > 0.);
+    }
+
+    #[test]
+    fn spring_settles_where_it_carries_the_weight() {
+        let mount = Some(Mount::Spring {
+            anchor: Vec2::ZERO,
+            stiffness: 100.,
+            damping: 20.,
+        });
+        let mut body = body_at(Vec2::ZERO);
+
+        for _ in 0..600 {
+            step_body(&mut body, mount, Vec2::new(0., -400.), 1. / 60.);
+        }
+
+        assert!(body.center.distance(Vec2::new(0., -4.)) < 0.01);
+        assert!(body.linear_velocity.length() < 0.01);
+    }
+
+    #[test]
+    fn rail_only_moves_along_its_direction() {
+        let direction = Vec2::new(1., 1.);
+        let mount = Some(Mount::Rail {
+            origin: Vec2::ZERO,
+            direction,
+            min: -10.,
+            max: 10.,
+        });
+        let mut body = body_at(Vec2::ZERO);
+        body.angular_velocity = 1.;
+
+        let (velocity, angular_velocity) =
+            integrate_rigid_body(body, mount, Vec2::new(0., -400.), 5., 0.1);
+        assert!(velocity.perp_dot(direction).abs() < 0.0001);
+        assert!((velocity.dot(direction.normalize()) + 400. * 0.1 / 2_f32.sqrt()).abs() < 0.001);
+        assert_eq!(angular_velocity, 0.);
+
+        // At the lower end it holds the body
+        body.center = direction.normalize() * -10.;
+        let (velocity, _) = integrate_rigid_body(body, mount, Vec2::new(0., -400.), 0., 0.1);
+        assert_eq!(velocity, Vec2::ZERO);
+    }
+}
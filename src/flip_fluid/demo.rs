@@ -0,0 +1,32 @@
+use bevy::prelude::*;
+
+// Features the tank is spawned with. The default is a plain tank of water, insert a Demo before
+// the plugin to switch on the features to look at, each of them works on its own.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct Demo {
+    // Spray, foam and bubbles
+    pub diffuse_particles: bool,
+    // Isolated droplets fly ballistically through the air
+    pub ballistic_droplets: bool,
+    pub vorticity_confinement: bool,
+    // A stove under the tank, the liquid conducts its heat and rises with it
+    pub heat_transfer: bool,
+    // A block of ice melting in the water
+    pub ice: bool,
+    // Smoke and steam rising from a source on the bottom, keys 0 and - switch to them
+    pub gas: bool,
+    // Air particles fill the rest of the tank and bubble up through the liquid
+    pub air: bool,
+    // A porous sponge soaking up the liquid, S squeezes it out
+    pub sponge: bool,
+    // A bed of sand on the bottom, eroded by the flow and deposited where it slows down
+    pub sediment: bool,
+    // The liquid clings to the glass walls and beads up on the waxed top and obstacle
+    pub wetting: bool,
+    // The same liquid simulated in the world frame next to the tank, for comparison
+    pub world_frame: bool,
+    // A shelf in the tank, with the loads of the liquid drawn on it
+    pub obstacle: bool,
+    // A partly filled bottle to flip instead of the tank, the mouse throws it and R stands it up
+    pub bottle_flip: bool,
+}
@@ -0,0 +1,315 @@
+use crate::flip_fluid::components::FlipFluid;
+use bevy::prelude::*;
+
+// Secondary spray, foam and bubble particles, based on
+// "Unified Spray, Foam and Bubbles for Particle-Based Fluids" (Ihmsen et al. 2012).
+// They are advected by the fluid but carry no mass and never feed back into the solver.
+
+#[derive(Component)]
+pub struct DiffuseParticle;
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum DiffuseKind {
+    #[default]
+    Spray,
+    Foam,
+    Bubble,
+}
+
+#[derive(Component)]
+pub struct DiffuseParticles {
+    max_particles: usize,
+    num_particles: usize,
+    particle_pos: Vec<f32>,
+    particle_vel: Vec<f32>,
+    particle_lifetime: Vec<f32>,
+    particle_kind: Vec<DiffuseKind>,
+
+    // Potentials are clamped to (min, max) and mapped to 0..1
+    trapped_air_range: (f32, f32),
+    wave_crest_range: (f32, f32),
+    kinetic_energy_range: (f32, f32),
+
+    // Max particles spawned per fluid particle and second
+    trapped_air_rate: f32,
+    wave_crest_rate: f32,
+
+    // Seconds a particle lives, the pool is fixed and would fill up with spray and bubbles
+    lifetime: f32,
+
+    // Relative fluid density below which particles are spray and above which they are bubbles
+    spray_density: f32,
+    bubble_density: f32,
+
+    buoyancy: f32,
+    // Rate per second at which bubbles take on the fluid velocity
+    bubble_drag: f32,
+    air_drag: f32,
+
+    seed: u32,
+}
+
+impl DiffuseParticles {
+    pub fn new(max_particles: usize) -> Self {
+        Self {
+            max_particles,
+            num_particles: 0,
+            particle_pos: vec![f32::default(); max_particles * 2],
+            particle_vel: vec![f32::default(); max_particles * 2],
+            particle_lifetime: vec![f32::default(); max_particles],
+            particle_kind: vec![DiffuseKind::default(); max_particles],
+            trapped_air_range: (5., 20.),
+            wave_crest_range: (0.1, 0.5),
+            kinetic_energy_range: (50., 500.),
+            trapped_air_rate: 20.,
+            wave_crest_rate: 20.,
+            lifetime: 2.,
+            spray_density: 0.3,
+            bubble_density: 0.9,
+            buoyancy: 1.5,
+            bubble_drag: 30.,
+            air_drag: 0.1,
+            seed: 0x9E37_79B9,
+        }
+    }
+
+    pub fn with_lifetime(mut self, lifetime: f32) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    pub fn with_air_drag(mut self, air_drag: f32) -> Self {
+        self.air_drag = air_drag;
+        self
+    }
+
+    pub fn num_particles(&self) -> usize {
+        self.num_particles
+    }
+
+    pub fn position(&self, i: usize) -> Vec2 {
+        Vec2::new(self.particle_pos[2 * i], self.particle_pos[2 * i + 1])
+    }
+
+    pub fn kind(&self, i: usize) -> DiffuseKind {
+        self.particle_kind[i]
+    }
+
+    // Remaining lifetime as a fraction of the initial lifetime
+    pub fn age_ratio(&self, i: usize) -> f32 {
+        (self.particle_lifetime[i] / self.lifetime).clamp(0., 1.)
+    }
+
+    // Acceleration is gravity plus fictitious forces, expressed in tank space.
+    pub fn simulate(&mut self, dt: f32, acceleration: Vec2, fluid: &FlipFluid) {
+        self.advect(dt, acceleration, fluid);
+        self.emit(dt, fluid);
+    }
+
+    fn advect(&mut self, dt: f32, acceleration: Vec2, fluid: &FlipFluid) {
+        let mut i = 0;
+
+        while i < self.num_particles {
+            let mut pos = self.position(i);
+            let mut vel = Vec2::new(self.particle_vel[2 * i], self.particle_vel[2 * i + 1]);
+
+            let rel_density = fluid.relative_density(pos);
+            let kind = if rel_density < self.spray_density {
+                DiffuseKind::Spray
+            } else if rel_density > self.bubble_density {
+                DiffuseKind::Bubble
+            } else {
+                DiffuseKind::Foam
+            };
+
+            match kind {
+                DiffuseKind::Spray => {
+                    vel += acceleration * dt;
+                    vel *= (1. - self.air_drag * dt).max(0.);
+                }
+                DiffuseKind::Foam => {
+                    vel = fluid.sample_velocity(pos);
+                }
+                DiffuseKind::Bubble => {
+                    let fluid_vel = fluid.sample_velocity(pos);
+                    vel += -self.buoyancy * acceleration * dt;
+                    vel += (self.bubble_drag * dt).min(1.) * (fluid_vel - vel);
+                }
+            }
+
+            pos += vel * dt;
+            self.particle_lifetime[i] -= dt;
+
+            if self.particle_lifetime[i] <= 0. || !pos.is_finite() || fluid.is_solid(pos) {
+                self.remove(i);
+                continue;
+            }
+
+            self.particle_pos[2 * i] = pos.x;
+            self.particle_pos[2 * i + 1] = pos.y;
+            self.particle_vel[2 * i] = vel.x;
+            self.particle_vel[2 * i + 1] = vel.y;
+            self.particle_kind[i] = kind;
+
+            i += 1;
+        }
+    }
+
+    fn emit(&mut self, dt: f32, fluid: &FlipFluid) {
+        let h = fluid.spacing();
+        let offsets = [Vec2::X * h, Vec2::NEG_X * h, Vec2::Y * h, Vec2::NEG_Y * h];
+
+        for p in 0..fluid.num_particles() {
+            if self.num_particles >= self.max_particles {
+                return;
+            }
+
+            if fluid.is_air(p) {
+                continue;
+            }
+
+            let pos = fluid.position(p);
+            let vel = fluid.velocity(p);
+
+            let kinetic_energy = 0.5 * vel.length_squared();
+            let energy = Self::clamp_potential(kinetic_energy, self.kinetic_energy_range);
+            if energy == 0. {
+                continue;
+            }
+
+            // Trapped air, from neighbourhood velocities moving towards each other
+            let mut trapped_air = 0.;
+            for offset in offsets {
+                let vel_ij = vel - fluid.sample_velocity(pos + offset);
+                let speed_ij = vel_ij.length();
+                if speed_ij > f32::EPSILON {
+                    let dir_ij = -offset.normalize();
+                    trapped_air += speed_ij * (1. - (vel_ij / speed_ij).dot(dir_ij)) * 0.25;
+                }
+            }
+
+            // Wave crests, from surface curvature where the fluid moves along the surface normal
+            let mut wave_crest = 0.;
+            if let Some(normal) = fluid.surface_normal(pos) {
+                if vel.normalize_or_zero().dot(normal) >= 0.6 {
+                    for offset in offsets {
+                        if let Some(neighbour_normal) = fluid.surface_normal(pos + offset) {
+                            wave_crest += (1. - normal.dot(neighbour_normal)) * 0.25;
+                        }
+                    }
+                }
+            }
+
+            let rate = self.trapped_air_rate
+                * Self::clamp_potential(trapped_air, self.trapped_air_range)
+                + self.wave_crest_rate * Self::clamp_potential(wave_crest, self.wave_crest_range);
+            let count = rate * energy * dt;
+
+            let mut num_spawn = count.floor() as usize;
+            if self.random() < count.fract() {
+                num_spawn += 1;
+            }
+
+            for _ in 0..num_spawn {
+                let angle = self.random() * std::f32::consts::TAU;
+                let radius = self.random().sqrt() * fluid.particle_radius() * 2.;
+                let spawn_pos = pos + Vec2::from_angle(angle) * radius;
+                if !self.spawn(spawn_pos, vel) {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn clamp_potential(value: f32, (min, max): (f32, f32)) -> f32 {
+        (value.clamp(min, max) - min) / (max - min)
+    }
+
+    fn spawn(&mut self, pos: Vec2, vel: Vec2) -> bool {
+        if self.num_particles >= self.max_particles {
+            return false;
+        }
+
+        let i = self.num_particles;
+        self.particle_pos[2 * i] = pos.x;
+        self.particle_pos[2 * i + 1] = pos.y;
+        self.particle_vel[2 * i] = vel.x;
+        self.particle_vel[2 * i + 1] = vel.y;
+        self.particle_lifetime[i] = self.lifetime;
+        self.particle_kind[i] = DiffuseKind::Spray;
+        self.num_particles += 1;
+
+        true
+    }
+
+    // Swap remove, the order of diffuse particles is irrelevant
+    fn remove(&mut self, i: usize) {
+        let last = self.num_particles - 1;
+        self.particle_pos.swap(2 * i, 2 * last);
+        self.particle_pos.swap(2 * i + 1, 2 * last + 1);
+        self.particle_vel.swap(2 * i, 2 * last);
+        self.particle_vel.swap(2 * i + 1, 2 * last + 1);
+        self.particle_lifetime.swap(i, last);
+        self.particle_kind.swap(i, last);
+        self.num_particles -= 1;
+    }
+
+    // Xorshift, returns a value in 0..1
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_tank() -> FlipFluid {
+        FlipFluid::new(1000., 30., 50., 2., 0.2, 0).with_solid_border()
+    }
+
+    #[test]
+    fn spray_moves_ballistically() {
+        let fluid = empty_tank();
+        let mut diffuse = DiffuseParticles::new(1).with_air_drag(0.);
+        diffuse.spawn(Vec2::new(15., 25.), Vec2::new(10., 0.));
+
+        diffuse.advect(0.1, Vec2::new(0., -100.), &fluid);
+
+        assert_eq!(diffuse.kind(0), DiffuseKind::Spray);
+        assert!((diffuse.position(0).x - 16.).abs() < 0.0001);
+        assert!((diffuse.position(0).y - 24.).abs() < 0.0001);
+    }
+
+    #[test]
+    fn particles_are_removed_in_solid_cells() {
+        let fluid = empty_tank();
+        let mut diffuse = DiffuseParticles::new(2);
+        diffuse.spawn(Vec2::new(15., 25.), Vec2::ZERO);
+        diffuse.spawn(Vec2::new(15., 1.), Vec2::new(0., -1.));
+
+        diffuse.advect(0.01, Vec2::ZERO, &fluid);
+
+        assert_eq!(diffuse.num_particles(), 1);
+        assert_eq!(diffuse.position(0), Vec2::new(15., 25.));
+    }
+
+    #[test]
+    fn spray_expires_after_its_lifetime() {
+        let fluid = empty_tank();
+        let mut diffuse = DiffuseParticles::new(1).with_lifetime(1.);
+        diffuse.spawn(Vec2::new(15., 25.), Vec2::ZERO);
+
+        for _ in 0..3 {
+            diffuse.advect(0.25, Vec2::ZERO, &fluid);
+        }
+        assert_eq!(diffuse.num_particles(), 1);
+        assert_eq!(diffuse.kind(0), DiffuseKind::Spray);
+
+        diffuse.advect(0.25, Vec2::ZERO, &fluid);
+        assert_eq!(diffuse.num_particles(), 0);
+    }
+}
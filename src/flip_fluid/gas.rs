@@ -0,0 +1,45 @@
+use bevy::prelude::*;
+
+// Smoke and steam on the MAC grid, see "Visual Simulation of Smoke" (Fedkiw et al. 2001).
+// Every non-solid cell is fluid, density and temperature are advected semi-Lagrangian.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gas {
+    // Downward pull per unit smoke density
+    pub weight: f32,
+    // Upward push per degree above the ambient temperature
+    pub buoyancy: f32,
+    // Fraction of the smoke density lost per second
+    pub dissipation: f32,
+    // Rate at which the gas relaxes to the ambient temperature, per second
+    pub cooling: f32,
+}
+
+impl Gas {
+    pub fn smoke() -> Self {
+        Self {
+            weight: 0.1,
+            buoyancy: 0.01,
+            dissipation: 0.1,
+            cooling: 0.5,
+        }
+    }
+
+    pub fn steam() -> Self {
+        Self {
+            weight: 0.,
+            buoyancy: 0.02,
+            dissipation: 0.5,
+            cooling: 1.,
+        }
+    }
+}
+
+// Emits smoke density per second and heats the gas inside a circle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GasSource {
+    pub center: Vec2,
+    pub radius: f32,
+    pub rate: f32,
+    pub temperature: f32,
+}
@@ -0,0 +1,49 @@
+use crate::flip_fluid::components::Wall;
+use bevy::prelude::*;
+
+// Anything the liquid can push on, obstacles are numbered in the order they were added
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Solid {
+    Wall(Wall),
+    Obstacle(usize),
+}
+
+// Force on a solid averaged over a step, with the torque about the origin and the point
+// the force acts on
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Load {
+    pub force: Vec2,
+    pub torque: f32,
+    pub center_of_pressure: Vec2,
+}
+
+// Sums the impulses from pressure and particle collisions during a step
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LoadAccumulator {
+    impulse: Vec2,
+    angular_impulse: f32,
+    weighted_point: Vec2,
+    weight: f32,
+}
+
+impl LoadAccumulator {
+    pub fn add(&mut self, point: Vec2, impulse: Vec2) {
+        let weight = impulse.length();
+        self.impulse += impulse;
+        self.angular_impulse += point.perp_dot(impulse);
+        self.weighted_point += point * weight;
+        self.weight += weight;
+    }
+
+    pub fn load(&self, dt: f32) -> Load {
+        Load {
+            force: self.impulse / dt,
+            torque: self.angular_impulse / dt,
+            center_of_pressure: if self.weight > 0. {
+                self.weighted_point / self.weight
+            } else {
+                Vec2::ZERO
+            },
+        }
+    }
+}
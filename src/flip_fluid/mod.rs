@@ -1,9 +1,28 @@
+mod bottle_flip;
+mod collision;
 mod components;
+pub mod demo;
+mod diffuse_particles;
+mod gas;
+mod loads;
+mod mpm;
+mod obstacle;
+mod pbf;
+mod porous;
+mod rheology;
+mod sediment;
 mod systems;
+mod wetting;
+mod world_frame;
 
+use crate::flip_fluid::demo::Demo;
 use crate::flip_fluid::systems::{
-    color_particles, integrate_position, integrate_rotation, move_particles, simulate_liquid,
-    spawn_tank, update_angular_velocity, update_linear_velocity,
+    collide_with_world, color_gas_cells, color_particles, draw_bottle_flip, draw_mounts,
+    draw_solid_loads, integrate_position, integrate_rigid_bodies, integrate_rotation,
+    judge_bottle_flip, move_diffuse_particles, move_grip, move_particles, reset_bottle,
+    simulate_liquid, simulate_world_frame_liquid, spawn_bottle, spawn_boundaries, spawn_tank,
+    squeeze_sponges, switch_advection, switch_color_mode, switch_material, switch_mount,
+    throw_bottle, update_angular_velocity, update_linear_velocity,
 };
 use bevy::prelude::*;
 
@@ -11,15 +30,46 @@ pub struct FlipFluidPlugin;
 
 impl Plugin for FlipFluidPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, spawn_tank);
-        app.add_systems(Update, (move_particles, color_particles));
+        app.init_resource::<Demo>();
+        app.add_systems(
+            Startup,
+            (
+                spawn_boundaries,
+                spawn_tank.run_if(|demo: Res<Demo>| !demo.bottle_flip),
+                spawn_bottle.run_if(|demo: Res<Demo>| demo.bottle_flip),
+            ),
+        );
+        app.add_systems(
+            Update,
+            (
+                move_particles,
+                color_particles,
+                move_diffuse_particles,
+                switch_material,
+                switch_color_mode,
+                switch_advection,
+                color_gas_cells,
+                squeeze_sponges,
+                switch_mount,
+                draw_mounts,
+                draw_solid_loads,
+                reset_bottle,
+                throw_bottle,
+                judge_bottle_flip,
+                draw_bottle_flip,
+            ),
+        );
         app.add_systems(
             PreUpdate,
             (
                 update_linear_velocity,
+                move_grip,
+                integrate_rigid_bodies,
                 integrate_position,
                 integrate_rotation,
+                collide_with_world,
                 simulate_liquid,
+                simulate_world_frame_liquid,
                 update_angular_velocity,
             )
                 .chain(),
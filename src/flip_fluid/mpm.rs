@@ -0,0 +1,437 @@
+use crate::flip_fluid::components::FlipFluid;
+use crate::utils::mechanics::frame_acceleration;
+use bevy::math::Mat2;
+use bevy::prelude::*;
+
+// Moving Least Squares Material Point Method, see
+// "A Moving Least Squares Material Point Method with Displacement Discontinuity and
+// Two-Way Rigid Body Coupling" (Hu et al. 2018).
+// Moduli are per unit density, like the rheology models.
+
+const NUM_SUB_STEPS: usize = 20;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MpmMaterial {
+    // Elastic neo-Hookean solid
+    Jelly,
+    // Fixed corotated with plasticity and hardening, see
+    // "A Material Point Method for Snow Simulation" (Stomakhin et al. 2013).
+    Snow,
+    // Same plasticity without hardening, deforms permanently
+    Clay,
+}
+
+impl MpmMaterial {
+    fn youngs_modulus(&self) -> f32 {
+        match self {
+            MpmMaterial::Jelly => 20000.,
+            MpmMaterial::Snow => 100000.,
+            MpmMaterial::Clay => 50000.,
+        }
+    }
+
+    fn poisson_ratio(&self) -> f32 {
+        match self {
+            MpmMaterial::Jelly => 0.3,
+            MpmMaterial::Snow => 0.2,
+            MpmMaterial::Clay => 0.3,
+        }
+    }
+
+    // Critical compression and stretch before the material yields
+    fn plastic_limits(&self) -> Option<(f32, f32)> {
+        match self {
+            MpmMaterial::Jelly => None,
+            MpmMaterial::Snow => Some((0.025, 0.0045)),
+            MpmMaterial::Clay => Some((0.01, 0.01)),
+        }
+    }
+
+    fn hardening(&self) -> f32 {
+        match self {
+            MpmMaterial::Snow => 10.,
+            _ => 0.,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct MpmSolver {
+    material: MpmMaterial,
+    mu: f32,
+    lambda: f32,
+
+    // Number of cols and rows of grid nodes
+    num_x: usize,
+    num_y: usize,
+    h: f32,
+    inv_spacing: f32,
+
+    grid_vel: Vec<f32>,
+    grid_mass: Vec<f32>,
+    grid_solid: Vec<bool>,
+
+    num_particles: usize,
+    // Index of each particle in the fluid it was taken from
+    fluid_index: Vec<usize>,
+    particle_pos: Vec<f32>,
+    particle_vel: Vec<f32>,
+    // Affine velocity field, 2x2 column major
+    particle_affine: Vec<Mat2>,
+    // Deformation gradient
+    particle_deformation: Vec<Mat2>,
+    // Plastic volume change
+    particle_plastic_j: Vec<f32>,
+    particle_volume: f32,
+}
+
+impl MpmSolver {
+    // Takes over the liquid particles and grid layout of a fluid
+    pub fn from_fluid(fluid: &FlipFluid, material: MpmMaterial) -> Self {
+        let h = fluid.spacing();
+        let (cells_x, cells_y) = fluid.grid_size();
+        let num_x = cells_x + 1;
+        let num_y = cells_y + 1;
+        let fluid_index: Vec<usize> = (0..fluid.num_particles())
+            .filter(|i| fluid.is_liquid(*i))
+            .collect();
+        let num_particles = fluid_index.len();
+
+        let e = material.youngs_modulus();
+        let nu = material.poisson_ratio();
+
+        let mut particle_pos = Vec::with_capacity(num_particles * 2);
+        let mut particle_vel = Vec::with_capacity(num_particles * 2);
+        for &i in &fluid_index {
+            particle_pos.extend(fluid.position(i).to_array());
+            particle_vel.extend(fluid.velocity(i).to_array());
+        }
+
+        let mut grid_solid = vec![false; num_x * num_y];
+        for i in 0..num_x {
+            for j in 0..num_y {
+                let node = Vec2::new(i as f32, j as f32) * h;
+                grid_solid[i * num_y + j] = fluid.is_solid(node);
+            }
+        }
+
+        let particle_diameter = 2. * fluid.particle_radius();
+
+        Self {
+            material,
+            mu: e / (2. * (1. + nu)),
+            lambda: e * nu / ((1. + nu) * (1. - 2. * nu)),
+            num_x,
+            num_y,
+            h,
+            inv_spacing: 1. / h,
+            grid_vel: vec![f32::default(); num_x * num_y * 2],
+            grid_mass: vec![f32::default(); num_x * num_y],
+            grid_solid,
+            num_particles,
+            fluid_index,
+            particle_pos,
+            particle_vel,
+            particle_affine: vec![Mat2::ZERO; num_particles],
+            particle_deformation: vec![Mat2::IDENTITY; num_particles],
+            particle_plastic_j: vec![1.; num_particles],
+            particle_volume: particle_diameter * particle_diameter,
+        }
+    }
+
+    pub fn num_particles(&self) -> usize {
+        self.num_particles
+    }
+
+    pub fn position(&self, i: usize) -> Vec2 {
+        Vec2::new(self.particle_pos[2 * i], self.particle_pos[2 * i + 1])
+    }
+
+    pub fn fluid_index(&self, i: usize) -> usize {
+        self.fluid_index[i]
+    }
+
+    pub fn velocity(&self, i: usize) -> Vec2 {
+        Vec2::new(self.particle_vel[2 * i], self.particle_vel[2 * i + 1])
+    }
+
+    // Takes the same tank inputs as FlipFluid::simulate
+    pub fn simulate(
+        &mut self,
+        dt: f32,
+        linear_acceleration: Vec2,
+        angular_acceleration: f32,
+        angular_velocity: f32,
+        rotation_center: Vec2,
+    ) {
+        let sdt = dt / NUM_SUB_STEPS as f32;
+
+        for _ in 0..NUM_SUB_STEPS {
+            self.particles_to_grid(sdt);
+            self.update_grid(
+                sdt,
+                linear_acceleration,
+                angular_acceleration,
+                angular_velocity,
+                rotation_center,
+            );
+            self.grid_to_particles(sdt);
+        }
+    }
+
+    // Quadratic B-spline weights and the lower left node of the 3x3 stencil
+    fn stencil(&self, pos: Vec2) -> (IVec2, Vec2, [Vec2; 3]) {
+        let cell = pos * self.inv_spacing;
+        let base = (cell - 0.5).floor();
+        let fx = cell - base;
+
+        let weights = [
+            0.5 * (1.5 - fx) * (1.5 - fx),
+            0.75 - (fx - 1.) * (fx - 1.),
+            0.5 * (fx - 0.5) * (fx - 0.5),
+        ];
+
+        (base.as_ivec2(), fx, weights)
+    }
+
+    fn stress(&self, i: usize) -> Mat2 {
+        let f = self.particle_deformation[i];
+        let j = f.determinant();
+
+        match self.material {
+            MpmMaterial::Jelly => {
+                // P F^T = mu (F F^T - I) + lambda ln(J) I
+                self.mu * (f * f.transpose() - Mat2::IDENTITY)
+                    + Mat2::from_diagonal(Vec2::splat(self.lambda * j.max(0.01).ln()))
+            }
+            MpmMaterial::Snow | MpmMaterial::Clay => {
+                let hardening =
+                    (self.material.hardening() * (1. - self.particle_plastic_j[i])).exp();
+                let mu = self.mu * hardening;
+                let lambda = self.lambda * hardening;
+                let (u, _, v) = svd(f);
+                let r = u * v.transpose();
+
+                // P F^T = 2 mu (F - R) F^T + lambda (J - 1) J I
+                2. * mu * (f - r) * f.transpose()
+                    + Mat2::from_diagonal(Vec2::splat(lambda * (j - 1.) * j))
+            }
+        }
+    }
+
+    fn particles_to_grid(&mut self, dt: f32) {
+        self.grid_vel.fill(0.);
+        self.grid_mass.fill(0.);
+
+        let mass = self.particle_volume;
+
+        for p in 0..self.num_particles {
+            let pos = self.position(p);
+            let vel = self.velocity(p);
+            let (base, fx, w) = self.stencil(pos);
+
+            let stress = -dt
+                * self.particle_volume
+                * 4.
+                * self.inv_spacing
+                * self.inv_spacing
+                * self.stress(p);
+            let affine = stress + mass * self.particle_affine[p];
+
+            for i in 0..3 {
+                for j in 0..3 {
+                    let Some(node) = self.node_index(base + IVec2::new(i, j)) else {
+                        continue;
+                    };
+
+                    let dpos = (Vec2::new(i as f32, j as f32) - fx) * self.h;
+                    let weight = w[i as usize].x * w[j as usize].y;
+                    let momentum = weight * (mass * vel + affine * dpos);
+
+                    self.grid_vel[2 * node] += momentum.x;
+                    self.grid_vel[2 * node + 1] += momentum.y;
+                    self.grid_mass[node] += weight * mass;
+                }
+            }
+        }
+    }
+
+    fn update_grid(
+        &mut self,
+        dt: f32,
+        linear_acceleration: Vec2,
+        angular_acceleration: f32,
+        angular_velocity: f32,
+        rotation_center: Vec2,
+    ) {
+        for i in 0..self.num_x {
+            for j in 0..self.num_y {
+                let node = i * self.num_y + j;
+                if self.grid_mass[node] <= 0. {
+                    continue;
+                }
+
+                let node_pos = Vec2::new(i as f32, j as f32) * self.h;
+                let mut vel = Vec2::new(self.grid_vel[2 * node], self.grid_vel[2 * node + 1])
+                    / self.grid_mass[node];
+
+                vel += dt
+                    * frame_acceleration(
+                        node_pos,
+                        linear_acceleration,
+                        angular_acceleration,
+                        angular_velocity,
+                        rotation_center,
+                    );
+
+                // Separating boundary conditions at the walls, sticky elsewhere in solids
+                if i < 2 || i > self.num_x - 3 || j < 2 || j > self.num_y - 3 {
+                    if (i < 2 && vel.x < 0.) || (i > self.num_x - 3 && vel.x > 0.) {
+                        vel.x = 0.;
+                    }
+                    if (j < 2 && vel.y < 0.) || (j > self.num_y - 3 && vel.y > 0.) {
+                        vel.y = 0.;
+                    }
+                } else if self.grid_solid[node] {
+                    vel = Vec2::ZERO;
+                }
+
+                self.grid_vel[2 * node] = vel.x;
+                self.grid_vel[2 * node + 1] = vel.y;
+            }
+        }
+    }
+
+    fn grid_to_particles(&mut self, dt: f32) {
+        let min = Vec2::splat(self.h);
+        let max = Vec2::new((self.num_x - 2) as f32, (self.num_y - 2) as f32) * self.h;
+
+        for p in 0..self.num_particles {
+            let (base, fx, w) = self.stencil(self.position(p));
+
+            let mut vel = Vec2::ZERO;
+            let mut affine = Mat2::ZERO;
+
+            for i in 0..3 {
+                for j in 0..3 {
+                    let Some(node) = self.node_index(base + IVec2::new(i, j)) else {
+                        continue;
+                    };
+
+                    let dpos = Vec2::new(i as f32, j as f32) - fx;
+                    let weight = w[i as usize].x * w[j as usize].y;
+                    let node_vel = Vec2::new(self.grid_vel[2 * node], self.grid_vel[2 * node + 1]);
+
+                    vel += weight * node_vel;
+                    affine += 4. * self.inv_spacing * weight * outer(node_vel, dpos);
+                }
+            }
+
+            let pos = (self.position(p) + dt * vel).clamp(min, max);
+
+            self.particle_pos[2 * p] = pos.x;
+            self.particle_pos[2 * p + 1] = pos.y;
+            self.particle_vel[2 * p] = vel.x;
+            self.particle_vel[2 * p + 1] = vel.y;
+            self.particle_affine[p] = affine;
+
+            let deformation = (Mat2::IDENTITY + dt * affine) * self.particle_deformation[p];
+            self.particle_deformation[p] = self.apply_plasticity(p, deformation);
+        }
+    }
+
+    fn apply_plasticity(&mut self, p: usize, deformation: Mat2) -> Mat2 {
+        let Some((compression, stretch)) = self.material.plastic_limits() else {
+            return deformation;
+        };
+
+        let (u, sigma, v) = svd(deformation);
+        let clamped = sigma.clamp(Vec2::splat(1. - compression), Vec2::splat(1. + stretch));
+
+        self.particle_plastic_j[p] *= (sigma.x * sigma.y) / (clamped.x * clamped.y);
+
+        u * Mat2::from_diagonal(clamped) * v.transpose()
+    }
+
+    fn node_index(&self, node: IVec2) -> Option<usize> {
+        if node.x < 0 || node.y < 0 || node.x >= self.num_x as i32 || node.y >= self.num_y as i32 {
+            return None;
+        }
+
+        Some(node.x as usize * self.num_y + node.y as usize)
+    }
+}
+
+fn outer(a: Vec2, b: Vec2) -> Mat2 {
+    Mat2::from_cols(a * b.x, a * b.y)
+}
+
+// Singular value decomposition of a 2x2 matrix, F = U diag(sigma) V^T with U and V rotations
+fn svd(f: Mat2) -> (Mat2, Vec2, Mat2) {
+    // Polar decomposition F = R S
+    let angle = (f.x_axis.y - f.y_axis.x).atan2(f.x_axis.x + f.y_axis.y);
+    let r = Mat2::from_angle(angle);
+    let s = r.transpose() * f;
+
+    // Symmetric eigen decomposition S = V diag(sigma) V^T
+    let (a, b, d) = (s.x_axis.x, s.x_axis.y, s.y_axis.y);
+    let phi = 0.5 * (2. * b).atan2(a - d);
+    let (sin, cos) = phi.sin_cos();
+    let sigma = Vec2::new(
+        a * cos * cos + 2. * b * sin * cos + d * sin * sin,
+        a * sin * sin - 2. * b * sin * cos + d * cos * cos,
+    );
+    let v = Mat2::from_angle(phi);
+
+    (r * v, sigma, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn svd_reconstructs_matrix() {
+        let f = Mat2::from_cols(Vec2::new(1.2, 0.3), Vec2::new(-0.4, 0.9));
+        let (u, sigma, v) = svd(f);
+        let reconstructed = u * Mat2::from_diagonal(sigma) * v.transpose();
+
+        assert!(reconstructed.abs_diff_eq(f, 0.0001));
+        assert!((u.determinant() - 1.).abs() < 0.0001);
+        assert!((v.determinant() - 1.).abs() < 0.0001);
+    }
+
+    #[test]
+    fn particles_fall_freely() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 4)
+            .with_solid_border()
+            .with_particles(2, 2);
+        for i in 0..fluid.num_particles() {
+            fluid.set_particle(i, fluid.position(i) + Vec2::new(10., 20.), Vec2::ZERO);
+        }
+        let mut solver = MpmSolver::from_fluid(&fluid, MpmMaterial::Jelly);
+        let start = solver.position(0);
+
+        solver.simulate(0.01, Vec2::new(0., -100.), 0., 0., Vec2::ZERO);
+
+        assert!((solver.velocity(0).y + 1.).abs() < 0.01);
+        assert!(solver.position(0).y < start.y);
+    }
+
+    #[test]
+    fn only_liquid_particles_are_taken_over() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 100)
+            .with_solid_border()
+            .with_particles(2, 2)
+            .with_air_particles(0.01);
+        let num_particles = fluid.num_particles();
+        fluid.set_particle(3, Vec2::new(12., 12.), Vec2::ZERO);
+
+        let solver = MpmSolver::from_fluid(&fluid, MpmMaterial::Jelly);
+
+        assert!(num_particles > 4);
+        assert_eq!(solver.num_particles(), 4);
+        assert_eq!(solver.fluid_index(3), 3);
+        assert_eq!(solver.position(3), Vec2::new(12., 12.));
+    }
+}
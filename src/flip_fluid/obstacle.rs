@@ -0,0 +1,16 @@
+use crate::flip_fluid::wetting::Wetting;
+use bevy::prelude::*;
+
+// Solid rectangle inside the tank, fixed in the tank frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Obstacle {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub wetting: Option<Wetting>,
+}
+
+impl Obstacle {
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+}
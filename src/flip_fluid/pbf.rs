@@ -0,0 +1,340 @@
+use crate::flip_fluid::components::FlipFluid;
+use crate::liquid_simulator::spatial_hash::SpatialHash;
+use crate::utils::mechanics::frame_acceleration;
+use bevy::prelude::*;
+use std::f32::consts::PI;
+
+// Position Based Fluids, see "Position Based Fluids" (Macklin and Müller 2013).
+// A purely particle based alternative to the grid solvers, particle masses are 1.
+
+const NUM_SUB_STEPS: usize = 4;
+const NUM_ITERS: usize = 4;
+
+// Kernel radius in particle radii
+const KERNEL_SCALE: f32 = 4.;
+
+// Constraint force mixing, relative to the constraint gradient of a particle at rest
+const RELAXATION: f32 = 0.1;
+
+// Artificial pressure against particle clustering, -k (W(r) / W(dq))^n
+const TENSILE_STRENGTH: f32 = 0.1;
+const TENSILE_EXPONENT: i32 = 4;
+const TENSILE_DISTANCE: f32 = 0.2;
+
+#[derive(Component)]
+pub struct PbfSolver {
+    kernel_radius: f32,
+    rest_density: f32,
+    relaxation: f32,
+    xsph_viscosity: f32,
+
+    min: Vec2,
+    max: Vec2,
+
+    spatial_hash: SpatialHash,
+    neighbours: Vec<Vec<usize>>,
+
+    // Index of each particle in the fluid it was taken from
+    fluid_index: Vec<usize>,
+    particle_pos: Vec<Vec2>,
+    particle_prev_pos: Vec<Vec2>,
+    particle_vel: Vec<Vec2>,
+    particle_lambda: Vec<f32>,
+    particle_delta: Vec<Vec2>,
+}
+
+impl PbfSolver {
+    // Takes over the liquid particles and tank size of a fluid
+    pub fn from_fluid(fluid: &FlipFluid) -> Self {
+        let h = fluid.spacing();
+        let r = fluid.particle_radius();
+        let (num_x, num_y) = fluid.grid_size();
+        let kernel_radius = KERNEL_SCALE * r;
+        let fluid_index: Vec<usize> = (0..fluid.num_particles())
+            .filter(|i| fluid.is_liquid(*i))
+            .collect();
+        let num_particles = fluid_index.len();
+
+        let particle_pos: Vec<Vec2> = fluid_index.iter().map(|i| fluid.position(*i)).collect();
+        let particle_vel = fluid_index.iter().map(|i| fluid.velocity(*i)).collect();
+
+        let (rest_density, rest_gradient) = Self::lattice_density(kernel_radius, r);
+
+        Self {
+            kernel_radius,
+            rest_density,
+            relaxation: RELAXATION * rest_gradient / (rest_density * rest_density),
+            xsph_viscosity: 0.1,
+            min: Vec2::splat(h + r),
+            max: Vec2::new((num_x - 1) as f32, (num_y - 1) as f32) * h - r,
+            spatial_hash: SpatialHash::new(
+                (num_x as f32 * h / kernel_radius) as usize + 1,
+                (num_y as f32 * h / kernel_radius) as usize + 1,
+                kernel_radius,
+            ),
+            neighbours: vec![vec![]; num_particles],
+            fluid_index,
+            particle_prev_pos: particle_pos.clone(),
+            particle_pos,
+            particle_vel,
+            particle_lambda: vec![f32::default(); num_particles],
+            particle_delta: vec![Vec2::default(); num_particles],
+        }
+    }
+
+    pub fn num_particles(&self) -> usize {
+        self.particle_pos.len()
+    }
+
+    pub fn fluid_index(&self, i: usize) -> usize {
+        self.fluid_index[i]
+    }
+
+    pub fn position(&self, i: usize) -> Vec2 {
+        self.particle_pos[i]
+    }
+
+    pub fn velocity(&self, i: usize) -> Vec2 {
+        self.particle_vel[i]
+    }
+
+    // Takes the same tank inputs as FlipFluid::simulate
+    pub fn simulate(
+        &mut self,
+        dt: f32,
+        linear_acceleration: Vec2,
+        angular_acceleration: f32,
+        angular_velocity: f32,
+        rotation_center: Vec2,
+    ) {
+        let sdt = dt / NUM_SUB_STEPS as f32;
+
+        for _ in 0..NUM_SUB_STEPS {
+            self.predict_positions(
+                sdt,
+                linear_acceleration,
+                angular_acceleration,
+                angular_velocity,
+                rotation_center,
+            );
+            self.find_neighbours();
+            for _ in 0..NUM_ITERS {
+                self.solve_density_constraints();
+            }
+            self.update_velocities(sdt);
+        }
+    }
+
+    fn predict_positions(
+        &mut self,
+        dt: f32,
+        linear_acceleration: Vec2,
+        angular_acceleration: f32,
+        angular_velocity: f32,
+        rotation_center: Vec2,
+    ) {
+        for i in 0..self.num_particles() {
+            let pos = self.particle_pos[i];
+            self.particle_vel[i] += dt
+                * frame_acceleration(
+                    pos,
+                    linear_acceleration,
+                    angular_acceleration,
+                    angular_velocity,
+                    rotation_center,
+                );
+            self.particle_prev_pos[i] = pos;
+            self.particle_pos[i] = (pos + dt * self.particle_vel[i]).clamp(self.min, self.max);
+        }
+    }
+
+    fn find_neighbours(&mut self) {
+        self.spatial_hash.populate(&self.particle_pos);
+
+        let h2 = self.kernel_radius * self.kernel_radius;
+        for i in 0..self.num_particles() {
+            let pos = self.particle_pos[i];
+            let mut neighbours = std::mem::take(&mut self.neighbours[i]);
+            neighbours.clear();
+            neighbours.extend(
+                self.spatial_hash
+                    .query(pos)
+                    .into_iter()
+                    .filter(|&j| j != i && pos.distance_squared(self.particle_pos[j]) < h2),
+            );
+            self.neighbours[i] = neighbours;
+        }
+    }
+
+    fn solve_density_constraints(&mut self) {
+        let h = self.kernel_radius;
+        let inv_rest_density = 1. / self.rest_density;
+
+        for i in 0..self.num_particles() {
+            let pos = self.particle_pos[i];
+            let mut density = poly6(0., h);
+            let mut gradient_i = Vec2::ZERO;
+            let mut gradient_sum = 0.;
+
+            for &j in &self.neighbours[i] {
+                let offset = pos - self.particle_pos[j];
+                density += poly6(offset.length_squared(), h);
+
+                let gradient_j = spiky_gradient(offset, h) * inv_rest_density;
+                gradient_i += gradient_j;
+                gradient_sum += gradient_j.length_squared();
+            }
+            gradient_sum += gradient_i.length_squared();
+
+            let constraint = density * inv_rest_density - 1.;
+            self.particle_lambda[i] = -constraint / (gradient_sum + self.relaxation);
+        }
+
+        let tensile_reference = poly6((TENSILE_DISTANCE * h).powi(2), h);
+
+        for i in 0..self.num_particles() {
+            let pos = self.particle_pos[i];
+            let lambda = self.particle_lambda[i];
+            let mut delta = Vec2::ZERO;
+
+            for &j in &self.neighbours[i] {
+                let offset = pos - self.particle_pos[j];
+                let tensile = -TENSILE_STRENGTH
+                    * (poly6(offset.length_squared(), h) / tensile_reference)
+                        .powi(TENSILE_EXPONENT);
+                delta += (lambda + self.particle_lambda[j] + tensile) * spiky_gradient(offset, h);
+            }
+
+            self.particle_delta[i] = delta * inv_rest_density;
+        }
+
+        for i in 0..self.num_particles() {
+            self.particle_pos[i] =
+                (self.particle_pos[i] + self.particle_delta[i]).clamp(self.min, self.max);
+        }
+    }
+
+    fn update_velocities(&mut self, dt: f32) {
+        let h = self.kernel_radius;
+
+        for i in 0..self.num_particles() {
+            self.particle_vel[i] = (self.particle_pos[i] - self.particle_prev_pos[i]) / dt;
+        }
+
+        // XSPH viscosity, blends towards the neighbourhood velocity
+        for i in 0..self.num_particles() {
+            let pos = self.particle_pos[i];
+            let vel = self.particle_vel[i];
+            let mut correction = Vec2::ZERO;
+
+            for &j in &self.neighbours[i] {
+                let weight = poly6(pos.distance_squared(self.particle_pos[j]), h);
+                correction += (self.particle_vel[j] - vel) * weight / self.rest_density;
+            }
+
+            self.particle_delta[i] = vel + self.xsph_viscosity * correction;
+        }
+
+        std::mem::swap(&mut self.particle_vel, &mut self.particle_delta);
+    }
+
+    // Density and squared constraint gradient of a particle in a hexagonal packing at rest,
+    // the same packing FlipFluid::with_particles uses.
+    fn lattice_density(h: f32, r: f32) -> (f32, f32) {
+        let dx = 2. * r;
+        let dy = 3_f32.sqrt() * r;
+        let rows = (h / dy).ceil() as i32;
+        let cols = (h / dx).ceil() as i32 + 1;
+
+        let mut density = 0.;
+        let mut gradient_i = Vec2::ZERO;
+        let mut gradient_sum = 0.;
+
+        for j in -rows..=rows {
+            for i in -cols..=cols {
+                let shift = if j % 2 == 0 { 0. } else { r };
+                let offset = Vec2::new(i as f32 * dx + shift, j as f32 * dy);
+                density += poly6(offset.length_squared(), h);
+
+                let gradient_j = spiky_gradient(offset, h);
+                gradient_i += gradient_j;
+                gradient_sum += gradient_j.length_squared();
+            }
+        }
+
+        (density, gradient_sum + gradient_i.length_squared())
+    }
+}
+
+// 2D poly6 kernel, from the squared distance
+fn poly6(r2: f32, h: f32) -> f32 {
+    let h2 = h * h;
+    if r2 >= h2 {
+        return 0.;
+    }
+
+    4. / (PI * h2.powi(4)) * (h2 - r2).powi(3)
+}
+
+// Gradient of the 2D spiky kernel, points from the neighbour towards the particle
+fn spiky_gradient(offset: Vec2, h: f32) -> Vec2 {
+    let r = offset.length();
+    if r >= h || r <= f32::EPSILON {
+        return Vec2::ZERO;
+    }
+
+    -30. / (PI * h.powi(5)) * (h - r) * (h - r) * offset / r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn particles_fall_freely() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 1)
+            .with_solid_border()
+            .with_particles(1, 1);
+        fluid.set_particle(0, Vec2::new(15., 25.), Vec2::ZERO);
+        let mut solver = PbfSolver::from_fluid(&fluid);
+
+        solver.simulate(0.01, Vec2::new(0., -100.), 0., 0., Vec2::ZERO);
+
+        assert!((solver.velocity(0).y + 1.).abs() < 0.01);
+        assert!(solver.position(0).y < 25.);
+    }
+
+    #[test]
+    fn compressed_particles_are_pushed_apart() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 9)
+            .with_solid_border()
+            .with_particles(3, 3);
+        for i in 0..9 {
+            let offset = Vec2::new((i / 3) as f32, (i % 3) as f32) * 0.1;
+            fluid.set_particle(i, Vec2::new(15., 25.) + offset, Vec2::ZERO);
+        }
+        let mut solver = PbfSolver::from_fluid(&fluid);
+
+        solver.simulate(0.01, Vec2::ZERO, 0., 0., Vec2::ZERO);
+
+        let spread = solver.position(8) - solver.position(0);
+        assert!(spread.x > 0.2 && spread.y > 0.2);
+    }
+
+    #[test]
+    fn only_liquid_particles_are_taken_over() {
+        let mut fluid = FlipFluid::new(1000., 30., 50., 2., 0.2, 100)
+            .with_solid_border()
+            .with_particles(2, 2)
+            .with_air_particles(0.01);
+        let num_particles = fluid.num_particles();
+        fluid.set_particle(3, Vec2::new(12., 12.), Vec2::ZERO);
+
+        let solver = PbfSolver::from_fluid(&fluid);
+
+        assert!(num_particles > 4);
+        assert_eq!(solver.num_particles(), 4);
+        assert_eq!(solver.fluid_index(3), 3);
+        assert_eq!(solver.position(3), Vec2::new(12., 12.));
+    }
+}
@@ -0,0 +1,21 @@
+use crate::flip_fluid::wetting::Wetting;
+use bevy::prelude::*;
+
+// Porous solid region that slows the flow with Darcy drag and soaks up liquid particles.
+// Porosity is the open volume fraction, permeability how easily liquid flows through the pores.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sponge {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub porosity: f32,
+    pub permeability: f32,
+    // Max number of particles stored inside
+    pub capacity: usize,
+    pub wetting: Option<Wetting>,
+}
+
+impl Sponge {
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+}
@@ -0,0 +1,91 @@
+// Generalized Newtonian fluids, where the viscosity depends on the local strain rate.
+// Viscosities and stresses are kinematic, i.e. divided by the fluid density.
+
+// Upper bound for the effective viscosity, unyielded material becomes (nearly) rigid
+pub const MAX_VISCOSITY: f32 = 10000.;
+
+// Strain rate regularization, avoids infinite viscosities in unsheared material
+const MIN_STRAIN_RATE: f32 = 0.001;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rheology {
+    Newtonian {
+        viscosity: f32,
+    },
+    // Shear thinning if flow_index < 1, shear thickening if flow_index > 1
+    PowerLaw {
+        consistency: f32,
+        flow_index: f32,
+    },
+    // Rigid below the yield stress, Newtonian with plastic_viscosity above it
+    Bingham {
+        yield_stress: f32,
+        plastic_viscosity: f32,
+    },
+}
+
+impl Rheology {
+    pub fn water() -> Self {
+        Rheology::Newtonian { viscosity: 0.01 }
+    }
+
+    pub fn paint() -> Self {
+        Rheology::PowerLaw {
+            consistency: 20.,
+            flow_index: 0.5,
+        }
+    }
+
+    pub fn cornstarch() -> Self {
+        Rheology::PowerLaw {
+            consistency: 0.5,
+            flow_index: 1.8,
+        }
+    }
+
+    pub fn ketchup() -> Self {
+        Rheology::Bingham {
+            yield_stress: 2000.,
+            plastic_viscosity: 5.,
+        }
+    }
+
+    pub fn effective_viscosity(&self, strain_rate: f32) -> f32 {
+        let strain_rate = strain_rate.max(MIN_STRAIN_RATE);
+
+        let viscosity = match *self {
+            Rheology::Newtonian { viscosity } => viscosity,
+            Rheology::PowerLaw {
+                consistency,
+                flow_index,
+            } => consistency * strain_rate.powf(flow_index - 1.),
+            Rheology::Bingham {
+                yield_stress,
+                plastic_viscosity,
+            } => plastic_viscosity + yield_stress / strain_rate,
+        };
+
+        viscosity.min(MAX_VISCOSITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_law_thins_and_thickens() {
+        let paint = Rheology::paint();
+        assert!(paint.effective_viscosity(10.) < paint.effective_viscosity(1.));
+
+        let cornstarch = Rheology::cornstarch();
+        assert!(cornstarch.effective_viscosity(10.) > cornstarch.effective_viscosity(1.));
+    }
+
+    #[test]
+    fn bingham_is_rigid_below_yield_stress() {
+        let ketchup = Rheology::ketchup();
+        assert_eq!(ketchup.effective_viscosity(0.), MAX_VISCOSITY);
+        assert!(ketchup.effective_viscosity(10000.) < 10.);
+    }
+}
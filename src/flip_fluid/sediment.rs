@@ -0,0 +1,26 @@
+use crate::flip_fluid::wetting::Wetting;
+
+// Erodible bed at the bottom of the tank. Shear from the flow above the bed picks up sediment
+// into the particles, which settle it back onto the bed where the flow is calm.
+// Bed heights and carried sediment are areas in tank units, so the total is conserved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sediment {
+    // Shear rate below which the bed does not erode
+    pub critical_shear: f32,
+    // Bed height eroded per second and unit of excess shear
+    pub erosion_rate: f32,
+    // Fraction of the carried sediment deposited per second next to the bed
+    pub settling_rate: f32,
+    pub wetting: Option<Wetting>,
+}
+
+impl Sediment {
+    pub fn sand() -> Self {
+        Self {
+            critical_shear: 10.,
+            erosion_rate: 0.002,
+            settling_rate: 0.5,
+            wetting: Some(Wetting::sand()),
+        }
+    }
+}
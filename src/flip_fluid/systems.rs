@@ -1,45 +1,345 @@
+use crate::flip_fluid::bottle_flip::{bottle_fluid, BottleFlip, FlightSample, FlipPhase};
+use crate::flip_fluid::collision::{
+    box_contacts, contact_impulse, merge_contacts, Collider, Impact, OrientedBox, StaticCollider,
+};
 use crate::flip_fluid::components::{
-    AngularVelocity, FlipFluid, LinearVelocity, LiquidParticle, PrevAngularVelocity,
-    PrevGlobalTransform, PrevLinearVelocity, Tank,
+    integrate_rigid_body, Advection, AngularVelocity, BodyState, ColorMode, ExternalForce,
+    FlipFluid, FluidReaction, Grip, LinearVelocity, LiquidParticle, Mount, RigidBody, SolidLoads,
+    Tank, Wall, WorldFrame,
 };
-use crate::utils::mechanics::center_of_rotation;
-use bevy::color::palettes::basic::{GREEN, YELLOW};
+use crate::flip_fluid::demo::Demo;
+use crate::flip_fluid::diffuse_particles::{DiffuseKind, DiffuseParticle, DiffuseParticles};
+use crate::flip_fluid::gas::{Gas, GasSource};
+use crate::flip_fluid::mpm::{MpmMaterial, MpmSolver};
+use crate::flip_fluid::obstacle::Obstacle;
+use crate::flip_fluid::pbf::PbfSolver;
+use crate::flip_fluid::porous::Sponge;
+use crate::flip_fluid::rheology::Rheology;
+use crate::flip_fluid::sediment::Sediment;
+use crate::flip_fluid::wetting::Wetting;
+use crate::flip_fluid::world_frame::MovingTank;
+use crate::utils::mechanics::MotionEstimator;
+use bevy::color::palettes::basic::{GREEN, RED, WHITE, YELLOW};
 use bevy::input::mouse::MouseMotion;
-use bevy::math::Affine3A;
 use bevy::prelude::*;
-use std::ops::Neg;
 
 const WIDTH: f32 = 30.;
 const HEIGHT: f32 = 50.;
+const SAND_ANGLE_OF_REPOSE: f32 = 34.;
+const ROOM_TEMPERATURE: f32 = 20.;
+const STOVE_TEMPERATURE: f32 = 80.;
+const FREEZING_POINT: f32 = 0.;
+const ICE_TEMPERATURE: f32 = -20.;
+const AIR_DENSITY: f32 = 0.05;
+const SAND_BED_HEIGHT: f32 = 1.5;
+const GRAVITY: f32 = 400.;
+// The world-frame tank repeats the motion of the tank, shifted to the side for comparison
+const WORLD_FRAME_OFFSET: Vec2 = Vec2::new(70., 0.);
+const WORLD_FRAME_GRID_SIZE: f32 = 100.;
+// Lighter than the water, held loosely enough for the sloshing to swing it
+const TANK_MASS: f32 = 50000.;
+const GRIP_STIFFNESS: f32 = 2e7;
+const GRIP_DAMPING: f32 = 2e6;
+const GRIP_ANGULAR_STIFFNESS: f32 = 1e9;
+const GRIP_ANGULAR_DAMPING: f32 = 1e8;
+// Frames of GlobalTransform history the tank motion is estimated from
+const MOTION_HISTORY: usize = 8;
+const HINGE_LIMIT: f32 = 0.8;
+const PENDULUM_LENGTH: f32 = 60.;
+const PENDULUM_FRICTION: f32 = 1e7;
+const SPRING_STIFFNESS: f32 = 5e6;
+const SPRING_DAMPING: f32 = 2e5;
+const RAIL_LENGTH: f32 = 120.;
+
+// Arrow length per unit of force the liquid exerts on a solid
+const LOAD_ARROW_SCALE: f32 = 1e-6;
+
+// Static boxes around the tank, it lands on the floor when the grip lets go
+const FLOOR_Y: f32 = -120.;
+const ARENA_HALF_WIDTH: f32 = 230.;
+const BOUNDARY_THICKNESS: f32 = 20.;
+const TANK_RESTITUTION: f32 = 0.2;
+const TANK_FRICTION: f32 = 0.6;
+// A plastic bottle is far lighter than the water in it
+const BOTTLE_MASS: f32 = 20000.;
+
+// World units per pixel of mouse motion, the camera shows 0.4 units per pixel
+const POINTER_SCALE: f32 = 0.4;
+
+enum Solver {
+    Flip {
+        rheology: Option<Rheology>,
+        angle_of_repose: Option<f32>,
+    },
+    Gas(Gas),
+    Mpm(MpmMaterial),
+    Pbf,
+}
+
+#[derive(Component)]
+pub struct GasCell;
 
 pub fn spawn_tank(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    demo: Res<Demo>,
 ) {
     let density = 1000.;
     let num_x = 30;
     let num_y = 30;
-    let max_particles = num_x * num_y;
+    let max_air_particles = if demo.air { 2500 } else { 0 };
+    let max_particles = num_x * num_y + max_air_particles;
+    let max_diffuse_particles = 1000;
+
+    let mut fluid = FlipFluid::new(density, WIDTH, HEIGHT, 2., 0.2, max_particles)
+        .with_solid_border()
+        .with_bottle_neck()
+        .with_particles(num_x, num_y)
+        .with_advection(Advection::RungeKutta3);
+    if demo.ballistic_droplets {
+        fluid = fluid.with_ballistic_droplets(2, 0.2);
+    }
+    if demo.vorticity_confinement {
+        fluid = fluid.with_vorticity_confinement(2.);
+    }
+    if demo.heat_transfer {
+        fluid = fluid
+            .with_heat_transfer(0.5, 0.005)
+            .with_wall_temperature(Wall::Bottom, STOVE_TEMPERATURE)
+            .with_wall_temperature(Wall::Left, ROOM_TEMPERATURE)
+            .with_wall_temperature(Wall::Right, ROOM_TEMPERATURE)
+            .with_wall_temperature(Wall::Top, ROOM_TEMPERATURE);
+    }
+    if demo.wetting {
+        fluid = fluid
+            .with_wall_wetting(Wall::Left, Wetting::glass())
+            .with_wall_wetting(Wall::Right, Wetting::glass())
+            .with_wall_wetting(Wall::Top, Wetting::wax())
+            .with_ice_wetting(Wetting::ice());
+    }
+    if demo.ice {
+        // The block melts by conduction from the water around it
+        fluid = fluid
+            .with_heat_transfer(0.5, 0.005)
+            .with_phase_change(FREEZING_POINT)
+            .with_temperature_block(Vec2::new(6., 7.), Vec2::new(10., 11.), ICE_TEMPERATURE);
+    }
+    if demo.gas {
+        fluid = fluid.with_gas_source(GasSource {
+            center: Vec2::new(WIDTH * 0.5, 4.),
+            radius: 3.,
+            rate: 2.,
+            temperature: STOVE_TEMPERATURE,
+        });
+    }
+    if demo.sponge {
+        fluid = fluid.with_sponge(Sponge {
+            min: Vec2::new(2., 20.),
+            max: Vec2::new(12., 26.),
+            porosity: 0.6,
+            permeability: 0.0003,
+            capacity: 150,
+            wetting: demo.wetting.then(Wetting::glass),
+        });
+    }
+    if demo.obstacle {
+        fluid = fluid.with_obstacle(Obstacle {
+            min: Vec2::new(18., 30.),
+            max: Vec2::new(28., 34.),
+            wetting: demo.wetting.then(Wetting::wax),
+        });
+    }
+    if demo.sediment {
+        fluid = fluid.with_sediment_bed(SAND_BED_HEIGHT, Sediment::sand());
+    }
+    if demo.air {
+        fluid = fluid.with_air_particles(AIR_DENSITY);
+    }
+    let (num_cells_x, num_cells_y) = fluid.grid_size();
+    let spacing = fluid.spacing();
+    let tank_size = Vec2::new(num_cells_x as f32, num_cells_y as f32) * spacing;
+
+    let mut tank = commands.spawn((
+        Mesh2d(meshes.add(Rectangle::new(WIDTH, HEIGHT))),
+        MeshMaterial2d(materials.add(Color::srgb(0.4, 0.4, 0.4))),
+        Transform::from_xyz(0., 0., -1.),
+        Visibility::default(),
+        fluid,
+        Tank,
+        LinearVelocity(Vec2::default()),
+        MotionEstimator::new(MOTION_HISTORY),
+        AngularVelocity(0.),
+        (
+            RigidBody::rectangle(TANK_MASS, Vec2::new(WIDTH, HEIGHT)),
+            ExternalForce::default(),
+            FluidReaction::default(),
+            SolidLoads::default(),
+            Collider {
+                half_size: Vec2::new(WIDTH, HEIGHT) * 0.5,
+                restitution: TANK_RESTITUTION,
+                friction: TANK_FRICTION,
+            },
+            Impact::default(),
+            Grip {
+                held: true,
+                target: Vec2::ZERO,
+                stiffness: GRIP_STIFFNESS,
+                damping: GRIP_DAMPING,
+                angular_stiffness: GRIP_ANGULAR_STIFFNESS,
+                angular_damping: GRIP_ANGULAR_DAMPING,
+            },
+        ),
+    ));
+    if demo.diffuse_particles {
+        tank.insert(
+            DiffuseParticles::new(max_diffuse_particles)
+                .with_lifetime(2.)
+                .with_air_drag(0.1),
+        );
+    }
+    tank.with_children(|parent| {
+        for _ in 0..max_particles {
+            parent.spawn((
+                Mesh2d(meshes.add(Rectangle::from_size(Vec2::splat(2.)))),
+                MeshMaterial2d(materials.add(Color::srgb(1., 1., 1.))),
+                LiquidParticle,
+            ));
+        }
+
+        if demo.diffuse_particles {
+            for _ in 0..max_diffuse_particles {
+                parent.spawn((
+                    Mesh2d(meshes.add(Rectangle::from_size(Vec2::splat(1.)))),
+                    // Default material blends, foam fades out with its lifetime
+                    MeshMaterial2d(materials.add(ColorMaterial::default())),
+                    Visibility::Hidden,
+                    DiffuseParticle,
+                ));
+            }
+        }
+
+        if demo.gas {
+            for _ in 0..num_cells_x * num_cells_y {
+                parent.spawn((
+                    Mesh2d(meshes.add(Rectangle::from_size(Vec2::splat(spacing)))),
+                    MeshMaterial2d(materials.add(ColorMaterial::default())),
+                    Visibility::Hidden,
+                    GasCell,
+                ));
+            }
+        }
+    });
+
+    if demo.world_frame {
+        let world_frame_fluid = FlipFluid::new(
+            density,
+            WORLD_FRAME_GRID_SIZE,
+            WORLD_FRAME_GRID_SIZE,
+            2.,
+            0.2,
+            num_x * num_y,
+        )
+        .with_solid_border()
+        .with_moving_tank(MovingTank::new(tank_size, spacing))
+        .with_particles(num_x, num_y);
+
+        // Grid origin in the lower left corner, the world-frame fluid positions need no offset
+        commands
+            .spawn((
+                Transform::from_translation(
+                    (WORLD_FRAME_OFFSET - WORLD_FRAME_GRID_SIZE * 0.5).extend(0.),
+                ),
+                Visibility::default(),
+                world_frame_fluid,
+                WorldFrame,
+                SolidLoads::default(),
+            ))
+            .with_children(|parent| {
+                for _ in 0..num_x * num_y {
+                    parent.spawn((
+                        Mesh2d(meshes.add(Rectangle::from_size(Vec2::splat(2.)))),
+                        MeshMaterial2d(materials.add(Color::srgb(1., 1., 1.))),
+                        LiquidParticle,
+                    ));
+                }
+            });
+    }
+}
+
+// Floor and walls of the arena, the tank and the bottle land on them
+pub fn spawn_boundaries(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let boundaries = [
+        (
+            Vec2::new(0., FLOOR_Y - BOUNDARY_THICKNESS * 0.5),
+            Vec2::new(
+                ARENA_HALF_WIDTH * 2. + BOUNDARY_THICKNESS * 2.,
+                BOUNDARY_THICKNESS,
+            ),
+        ),
+        (
+            Vec2::new(-ARENA_HALF_WIDTH - BOUNDARY_THICKNESS * 0.5, 0.),
+            Vec2::new(BOUNDARY_THICKNESS, -FLOOR_Y * 2.),
+        ),
+        (
+            Vec2::new(ARENA_HALF_WIDTH + BOUNDARY_THICKNESS * 0.5, 0.),
+            Vec2::new(BOUNDARY_THICKNESS, -FLOOR_Y * 2.),
+        ),
+    ];
+    for (center, size) in boundaries {
+        commands.spawn((
+            Mesh2d(meshes.add(Rectangle::from_size(size))),
+            MeshMaterial2d(materials.add(Color::srgb(0.25, 0.25, 0.25))),
+            Transform::from_translation(center.extend(-1.)),
+            StaticCollider {
+                half_size: size * 0.5,
+                restitution: 0.1,
+                friction: 0.8,
+            },
+        ));
+    }
+}
+
+// Partly filled bottle standing on the floor, for the bottle-flip minigame
+pub fn spawn_bottle(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let fluid = bottle_fluid(WIDTH, HEIGHT);
+    let num_particles = fluid.num_particles();
 
     commands
         .spawn((
             Mesh2d(meshes.add(Rectangle::new(WIDTH, HEIGHT))),
             MeshMaterial2d(materials.add(Color::srgb(0.4, 0.4, 0.4))),
-            Transform::from_xyz(0., 0., -1.),
+            Transform::from_xyz(0., FLOOR_Y + HEIGHT * 0.5, -1.),
             Visibility::default(),
-            FlipFluid::new(density, WIDTH, HEIGHT, 2., 0.2, max_particles)
-                .with_solid_border()
-                .with_particles(num_x, num_y),
+            fluid,
             Tank,
             LinearVelocity(Vec2::default()),
-            PrevLinearVelocity(Vec2::default()),
-            PrevGlobalTransform(Affine3A::default()),
+            MotionEstimator::new(MOTION_HISTORY),
             AngularVelocity(0.),
-            PrevAngularVelocity(0.),
+            (
+                RigidBody::rectangle(BOTTLE_MASS, Vec2::new(WIDTH, HEIGHT)),
+                ExternalForce::default(),
+                FluidReaction::default(),
+                SolidLoads::default(),
+                Collider {
+                    half_size: Vec2::new(WIDTH, HEIGHT) * 0.5,
+                    restitution: TANK_RESTITUTION,
+                    friction: TANK_FRICTION,
+                },
+                Impact::default(),
+                BottleFlip::default(),
+            ),
         ))
         .with_children(|parent| {
-            for _ in 0..max_particles {
+            for _ in 0..num_particles {
                 parent.spawn((
                     Mesh2d(meshes.add(Rectangle::from_size(Vec2::splat(2.)))),
                     MeshMaterial2d(materials.add(Color::srgb(1., 1., 1.))),
@@ -50,14 +350,48 @@ pub fn spawn_tank(
 }
 
 pub fn move_particles(
-    fluid_query: Query<(&FlipFluid, &Children)>,
-    mut particle_query: Query<&mut Transform, With<LiquidParticle>>,
+    fluid_query: Query<(
+        &FlipFluid,
+        Option<&MpmSolver>,
+        Option<&PbfSolver>,
+        &Children,
+        Has<WorldFrame>,
+    )>,
+    mut particle_query: Query<(&mut Transform, &mut Visibility), With<LiquidParticle>>,
 ) {
-    let offset = Vec2::new(WIDTH * -0.5, HEIGHT * -0.5);
-    for (fluid, children) in &fluid_query {
+    for (fluid, mpm, pbf, children, world_frame) in &fluid_query {
+        let offset = if world_frame {
+            Vec2::ZERO
+        } else {
+            Vec2::new(WIDTH * -0.5, HEIGHT * -0.5)
+        };
+
+        // The liquid particles an active solver took over, the rest stay where the fluid has them
+        let mut taken_over = vec![None; fluid.num_particles()];
+        if let Some(mpm) = mpm {
+            for k in 0..mpm.num_particles() {
+                taken_over[mpm.fluid_index(k)] = Some(mpm.position(k));
+            }
+        }
+        if let Some(pbf) = pbf {
+            for k in 0..pbf.num_particles() {
+                taken_over[pbf.fluid_index(k)] = Some(pbf.position(k));
+            }
+        }
+
         for (i, child) in children.iter().enumerate() {
-            if let Ok(mut transform) = particle_query.get_mut(*child) {
-                transform.translation = (fluid.position(i) + offset).extend(1.);
+            if let Ok((mut transform, mut visibility)) = particle_query.get_mut(*child) {
+                *visibility = if fluid.is_gas() || fluid.is_air(i) {
+                    Visibility::Hidden
+                } else {
+                    Visibility::Inherited
+                };
+                let position = taken_over
+                    .get(i)
+                    .copied()
+                    .flatten()
+                    .unwrap_or_else(|| fluid.position(i));
+                transform.translation = (position + offset).extend(1.);
             }
         }
     }
@@ -79,16 +413,95 @@ pub fn color_particles(
     }
 }
 
+pub fn move_diffuse_particles(
+    fluid_query: Query<(&DiffuseParticles, &Children)>,
+    mut particle_query: Query<
+        (
+            &mut Transform,
+            &mut Visibility,
+            &MeshMaterial2d<ColorMaterial>,
+        ),
+        With<DiffuseParticle>,
+    >,
+    mut colors: ResMut<Assets<ColorMaterial>>,
+) {
+    let offset = Vec2::new(WIDTH * -0.5, HEIGHT * -0.5);
+    for (diffuse, children) in &fluid_query {
+        let mut i = 0;
+        let mut iter = particle_query.iter_many_mut(children);
+        while let Some((mut transform, mut visibility, color_material)) = iter.fetch_next() {
+            if i >= diffuse.num_particles() {
+                *visibility = Visibility::Hidden;
+                continue;
+            }
+
+            transform.translation = (diffuse.position(i) + offset).extend(2.);
+            *visibility = Visibility::Inherited;
+
+            if let Some(material) = colors.get_mut(color_material.id()) {
+                material.color = match diffuse.kind(i) {
+                    DiffuseKind::Spray => Color::srgb(0.9, 0.95, 1.),
+                    DiffuseKind::Foam => Color::srgba(1., 1., 1., diffuse.age_ratio(i)),
+                    DiffuseKind::Bubble => Color::srgb(0.6, 0.8, 1.),
+                };
+            }
+
+            i += 1;
+        }
+    }
+}
+
+pub fn color_gas_cells(
+    fluid_query: Query<(&FlipFluid, &Children)>,
+    mut cell_query: Query<
+        (
+            &mut Transform,
+            &mut Visibility,
+            &MeshMaterial2d<ColorMaterial>,
+        ),
+        With<GasCell>,
+    >,
+    mut colors: ResMut<Assets<ColorMaterial>>,
+) {
+    let offset = Vec2::new(WIDTH * -0.5, HEIGHT * -0.5);
+    for (fluid, children) in &fluid_query {
+        let (_, num_cells_y) = fluid.grid_size();
+        let spacing = fluid.spacing();
+
+        let mut cell_nr = 0;
+        let mut iter = cell_query.iter_many_mut(children);
+        while let Some((mut transform, mut visibility, color_material)) = iter.fetch_next() {
+            let cell = Vec2::new(
+                (cell_nr / num_cells_y) as f32,
+                (cell_nr % num_cells_y) as f32,
+            );
+            transform.translation = ((cell + 0.5) * spacing + offset).extend(3.);
+
+            if fluid.is_gas() {
+                *visibility = Visibility::Inherited;
+                if let Some(material) = colors.get_mut(color_material.id()) {
+                    material.color = fluid.cell_color(cell_nr);
+                }
+            } else {
+                *visibility = Visibility::Hidden;
+            }
+
+            cell_nr += 1;
+        }
+    }
+}
+
 pub fn simulate_liquid(
     mut fluid_query: Query<(
         &mut FlipFluid,
         &GlobalTransform,
-        &Transform,
-        &mut PrevLinearVelocity,
-        &LinearVelocity,
-        &AngularVelocity,
-        &mut PrevAngularVelocity,
-        &mut PrevGlobalTransform,
+        &mut MotionEstimator,
+        Option<&mut DiffuseParticles>,
+        Option<&mut MpmSolver>,
+        Option<&mut PbfSolver>,
+        Option<&mut FluidReaction>,
+        Option<&mut SolidLoads>,
+        Option<&Impact>,
     )>,
     time: Res<Time>,
     mut gizmos: Gizmos,
@@ -96,58 +509,82 @@ pub fn simulate_liquid(
     for (
         mut fluid,
         global_transform,
-        transform,
-        mut prev_linear_velocity,
-        linear_velocity,
-        angular_velocity,
-        mut prev_angular_velocity,
-        mut prev_global_transform,
+        mut motion_estimator,
+        diffuse_particles,
+        mpm,
+        pbf,
+        mut reaction,
+        solid_loads,
+        impact,
     ) in &mut fluid_query
     {
-        let gravity_angle = (transform.rotation * Vec3::NEG_Y)
-            .xy()
-            .angle_to(Vec2::NEG_X);
-        let gravity_vec = Vec2::from_angle(gravity_angle);
-        let gravity = gravity_vec * 400.;
+        let impact = impact.copied().unwrap_or_default();
+        if !impact.is_empty() {
+            motion_estimator.clear();
+        }
 
-        let linear_velocity_delta = linear_velocity.0 - prev_linear_velocity.0;
-        let tank_acceleration = linear_velocity_delta / time.delta_secs();
-        prev_linear_velocity.0 = linear_velocity.0;
+        // The tank frame moves with the world transform, whichever ancestor is moving it
+        motion_estimator.push(time.elapsed_secs(), global_transform);
+        let motion = motion_estimator.estimate();
+        let to_tank = global_transform.rotation().inverse();
+        let gravity = (to_tank * Vec3::NEG_Y).xy() * GRAVITY;
+        let mut tank_acceleration = (to_tank * motion.linear_acceleration.extend(0.)).xy();
+        let angular_velocity = motion.angular_velocity;
+        let mut angular_acceleration = motion.angular_acceleration;
 
-        let angular_velocity_delta = angular_velocity.0 - prev_angular_velocity.0;
-        let angular_acceleration = angular_velocity_delta / time.delta_secs();
-        prev_angular_velocity.0 = angular_velocity.0;
+        // A collision changes the velocity within the frame, the liquid feels it at once
+        if !impact.is_empty() && time.delta_secs() > 0. {
+            tank_acceleration +=
+                (to_tank * impact.linear_velocity.extend(0.)).xy() / time.delta_secs();
+            angular_acceleration += impact.angular_velocity / time.delta_secs();
+        }
 
         let linear_acceleration = if tank_acceleration.is_finite() {
-            gravity + tank_acceleration.neg()
+            gravity - tank_acceleration
         } else {
             gravity
         };
 
-        let point_a = prev_global_transform.0.translation.xy();
-        let velocity_a = global_transform.translation().xy() - point_a;
-        let point_b = prev_global_transform.0.transform_point3(Vec3::Y * 1.).xy();
-        let velocity_b = global_transform.transform_point(Vec3::Y * 1.).xy() - point_b;
-        prev_global_transform.0 = global_transform.affine();
-
-        let pole = center_of_rotation(point_a, velocity_a, point_b, velocity_b);
+        // The estimated acceleration is that of the tank center, the frame turns around it
         let tank_offset = Vec2::new(WIDTH * 0.5, HEIGHT * 0.5);
-        let rotation_center = tank_offset
-            + global_transform
-                .affine()
-                .inverse()
-                .transform_point(pole.extend(0.))
-                .xy();
+        let rotation_center = tank_offset;
+
+        if let Some(pole) = motion.center_of_rotation {
+            gizmos.circle_2d(Isometry2d::from(pole), 4., YELLOW);
+        }
+
+        if let Some(reaction) = &mut reaction {
+            **reaction = FluidReaction::default();
+        }
 
-        println!("local rotation center {:.2}", rotation_center);
-        gizmos.circle_2d(Isometry2d::from(pole), 4., YELLOW);
+        // Particle solvers replace the grid solver while they are active
+        if let Some(mut mpm) = mpm {
+            mpm.simulate(
+                time.delta_secs(),
+                linear_acceleration,
+                angular_acceleration,
+                angular_velocity,
+                rotation_center,
+            );
+            continue;
+        }
+        if let Some(mut pbf) = pbf {
+            pbf.simulate(
+                time.delta_secs(),
+                linear_acceleration,
+                angular_acceleration,
+                angular_velocity,
+                rotation_center,
+            );
+            continue;
+        }
 
         fluid.simulate(
             time.delta_secs(),
             linear_acceleration.x,
             linear_acceleration.y,
             angular_acceleration,
-            angular_velocity.0,
+            angular_velocity,
             rotation_center.x,
             rotation_center.y,
             0.9,
@@ -156,14 +593,145 @@ pub fn simulate_liquid(
             1.9,
             true,
             true,
-            &mut gizmos,
         );
+
+        if let Some(mut solid_loads) = solid_loads {
+            solid_loads.0 = fluid.world_solid_loads(global_transform);
+        }
+
+        if let Some(reaction) = &mut reaction {
+            let tank_center = tank_offset;
+            let properties = fluid.mass_properties(tank_center);
+            let (mass, moment_of_inertia) = (properties.mass, properties.moment_of_inertia);
+            let (force, torque) = fluid.tank_reaction(
+                tank_center,
+                gravity,
+                linear_acceleration,
+                angular_acceleration,
+            );
+
+            if force.is_finite() && torque.is_finite() {
+                **reaction = FluidReaction {
+                    force: (global_transform.rotation() * force.extend(0.)).xy(),
+                    torque,
+                    mass,
+                    moment_of_inertia,
+                };
+            }
+        }
+
+        let bed = fluid.bed_surface().into_iter().map(|point| {
+            global_transform
+                .transform_point((point - tank_offset).extend(0.))
+                .xy()
+        });
+        gizmos.linestrip_2d(bed, Color::srgb(0.76, 0.6, 0.4));
+
+        let (angle, _, _) = global_transform.rotation().to_euler(EulerRot::ZYX);
+        for obstacle in fluid.obstacles() {
+            let center = global_transform
+                .transform_point(((obstacle.min + obstacle.max) * 0.5 - tank_offset).extend(0.))
+                .xy();
+            gizmos.rect_2d(
+                Isometry2d::new(center, Rot2::radians(angle)),
+                obstacle.max - obstacle.min,
+                GREEN,
+            );
+        }
+
+        let properties = fluid.world_mass_properties(
+            global_transform.translation().xy(),
+            global_transform,
+            motion.linear_velocity,
+            motion.angular_velocity,
+        );
+        gizmos.cross_2d(Isometry2d::from(properties.center_of_mass), 2., YELLOW);
+
+        if let Some(mut diffuse_particles) = diffuse_particles.filter(|_| !fluid.is_gas()) {
+            diffuse_particles.simulate(time.delta_secs(), linear_acceleration, &fluid);
+        }
+    }
+}
+
+// Drives the world-frame tank with the motion of the tank, gravity is the only force
+pub fn simulate_world_frame_liquid(
+    tank_query: Query<
+        (
+            &Transform,
+            &LinearVelocity,
+            &AngularVelocity,
+            Has<RigidBody>,
+        ),
+        With<Tank>,
+    >,
+    mut fluid_query: Query<
+        (
+            &mut FlipFluid,
+            &Transform,
+            &GlobalTransform,
+            Option<&mut SolidLoads>,
+        ),
+        (With<WorldFrame>, Without<Tank>),
+    >,
+    time: Res<Time>,
+    mut gizmos: Gizmos,
+) {
+    let Ok((tank_transform, linear_velocity, angular_velocity, rigid_body)) =
+        tank_query.get_single()
+    else {
+        return;
+    };
+    let (angle, _, _) = tank_transform.rotation.to_euler(EulerRot::ZYX);
+    let position = tank_transform.translation.xy();
+    // Kinematic tanks rotate around the world origin, rigid bodies move with their center
+    let velocity = if rigid_body {
+        linear_velocity.0
+    } else {
+        linear_velocity.0 + position.perp() * angular_velocity.0
+    };
+
+    for (mut fluid, transform, global_transform, solid_loads) in &mut fluid_query {
+        let origin = transform.translation.xy();
+        fluid.set_tank_motion(
+            position + WORLD_FRAME_OFFSET - origin,
+            angle,
+            velocity,
+            angular_velocity.0,
+        );
+
+        fluid.simulate(
+            time.delta_secs(),
+            0.,
+            -GRAVITY,
+            0.,
+            0.,
+            0.,
+            0.,
+            0.9,
+            100,
+            2,
+            1.9,
+            true,
+            true,
+        );
+
+        if let Some(mut solid_loads) = solid_loads {
+            solid_loads.0 = fluid.world_solid_loads(global_transform);
+        }
+
+        if let Some(tank) = fluid.moving_tank() {
+            gizmos.rect_2d(
+                Isometry2d::new(tank.center + origin, Rot2::radians(angle)),
+                tank.size,
+                GREEN,
+            );
+        }
     }
 }
 
 pub fn update_linear_velocity(
     mut evr_motion: EventReader<MouseMotion>,
-    mut physics_query: Query<&mut LinearVelocity>,
+    mut physics_query: Query<&mut LinearVelocity, Without<RigidBody>>,
     time: Res<Time>,
 ) {
     for mut linear_velocity in &mut physics_query {
@@ -174,7 +742,10 @@ pub fn update_linear_velocity(
     }
 }
 
-pub fn update_angular_velocity(mut physics_query: Query<&mut AngularVelocity>, time: Res<Time>) {
+pub fn update_angular_velocity(
+    mut physics_query: Query<&mut AngularVelocity, Without<RigidBody>>,
+    time: Res<Time>,
+) {
     for mut angular_velocity in &mut physics_query {
         angular_velocity.0 = (time.elapsed_secs() * 0.2).sin() * 8.;
         // angular_velocity.0 += 0.001;
@@ -182,13 +753,120 @@ pub fn update_angular_velocity(mut physics_query: Query<&mut AngularVelocity>, t
     }
 }
 
+// Mouse motion moves the grip, which pulls the tank along. Mounted tanks are pushed along
+// with the mouse instead.
+pub fn move_grip(
+    mut evr_motion: EventReader<MouseMotion>,
+    mut grip_query: Query<(
+        &mut Grip,
+        &Transform,
+        &LinearVelocity,
+        &AngularVelocity,
+        &mut ExternalForce,
+        Has<Mount>,
+    )>,
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+) {
+    let delta = evr_motion.read().map(|ev| ev.delta).sum::<Vec2>() * Vec2::new(1., -1.);
+
+    for (mut grip, transform, linear_velocity, angular_velocity, mut external_force, mounted) in
+        &mut grip_query
+    {
+        if mounted {
+            grip.target = transform.translation.xy();
+            external_force.force = if time.delta_secs() > 0. {
+                grip.damping * delta / time.delta_secs()
+            } else {
+                Vec2::ZERO
+            };
+            external_force.torque = 0.;
+            continue;
+        }
+
+        if keys.just_pressed(KeyCode::KeyG) {
+            grip.held = !grip.held;
+        }
+        if !grip.held {
+            grip.target = transform.translation.xy();
+            external_force.force = Vec2::ZERO;
+            external_force.torque = 0.;
+            continue;
+        }
+
+        grip.target += delta;
+
+        let (angle, _, _) = transform.rotation.to_euler(EulerRot::ZYX);
+        external_force.force = grip.stiffness * (grip.target - transform.translation.xy())
+            - grip.damping * linear_velocity.0;
+        external_force.torque =
+            -grip.angular_stiffness * angle - grip.angular_damping * angular_velocity.0;
+    }
+}
+
+pub fn integrate_rigid_bodies(
+    mut body_query: Query<(
+        &RigidBody,
+        &ExternalForce,
+        &FluidReaction,
+        Option<&Mount>,
+        &Transform,
+        &mut LinearVelocity,
+        &mut AngularVelocity,
+    )>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+
+    for (
+        body,
+        external_force,
+        reaction,
+        mount,
+        transform,
+        mut linear_velocity,
+        mut angular_velocity,
+    ) in &mut body_query
+    {
+        let (angle, _, _) = transform.rotation.to_euler(EulerRot::ZYX);
+        let state = BodyState {
+            mass: body.mass + reaction.mass,
+            moment_of_inertia: body.moment_of_inertia + reaction.moment_of_inertia,
+            center: transform.translation.xy(),
+            angle,
+            linear_velocity: linear_velocity.0,
+            angular_velocity: angular_velocity.0,
+        };
+
+        let weight = Vec2::NEG_Y * GRAVITY * body.mass;
+        let force = external_force.force + reaction.force + weight;
+        let torque = external_force.torque + reaction.torque;
+
+        (linear_velocity.0, angular_velocity.0) =
+            integrate_rigid_body(state, mount.copied(), force, torque, dt);
+    }
+}
+
 pub fn integrate_rotation(
-    mut physics_query: Query<(&mut Transform, &AngularVelocity)>,
+    mut physics_query: Query<(
+        &mut Transform,
+        &AngularVelocity,
+        Has<RigidBody>,
+        Option<&Mount>,
+    )>,
     time: Res<Time>,
 ) {
-    for (mut transform, angular_velocity) in &mut physics_query {
+    for (mut transform, angular_velocity, rigid_body, mount) in &mut physics_query {
+        // Rigid bodies turn around their pivot or center of mass
+        let center = if let Some(pivot) = mount.and_then(Mount::pivot) {
+            pivot.extend(transform.translation.z)
+        } else if rigid_body {
+            transform.translation
+        } else {
+            Vec3::new(0., 0., 0.)
+        };
         transform.rotate_around(
-            Vec3::new(0., 0., 0.),
+            center,
             Quat::from_rotation_z(angular_velocity.0 * time.delta_secs()),
         );
 
@@ -199,10 +877,428 @@ pub fn integrate_rotation(
 }
 
 pub fn integrate_position(
-    mut physics_query: Query<(&mut Transform, &LinearVelocity)>,
+    mut physics_query: Query<(&mut Transform, &LinearVelocity, Option<&Mount>)>,
     time: Res<Time>,
 ) {
-    for (mut transform, linear_velocity) in &mut physics_query {
+    for (mut transform, linear_velocity, mount) in &mut physics_query {
+        // Pinned bodies are moved by turning around the pivot
+        if mount.and_then(Mount::pivot).is_some() {
+            continue;
+        }
         transform.translation += linear_velocity.0.extend(0.) * time.delta_secs();
     }
 }
+
+// Bounces rigid bodies off the static colliders. Bodies pinned to a pivot are held by it.
+pub fn collide_with_world(
+    mut body_query: Query<(
+        &RigidBody,
+        &Collider,
+        &FluidReaction,
+        Option<&Mount>,
+        &mut Transform,
+        &mut LinearVelocity,
+        &mut AngularVelocity,
+        &mut Impact,
+    )>,
+    collider_query: Query<(&StaticCollider, &GlobalTransform)>,
+) {
+    for (
+        body,
+        collider,
+        reaction,
+        mount,
+        mut transform,
+        mut linear_velocity,
+        mut angular_velocity,
+        mut impact,
+    ) in &mut body_query
+    {
+        *impact = Impact::default();
+        if mount.and_then(Mount::pivot).is_some() {
+            continue;
+        }
+
+        // The liquid is carried along by the impact
+        let mass = body.mass + reaction.mass;
+        let moment_of_inertia = body.moment_of_inertia + reaction.moment_of_inertia;
+
+        for (solid, solid_transform) in &collider_query {
+            let (angle, _, _) = transform.rotation.to_euler(EulerRot::ZYX);
+            let shape = OrientedBox {
+                center: transform.translation.xy(),
+                angle,
+                half_size: collider.half_size,
+            };
+            let (solid_angle, _, _) = solid_transform.rotation().to_euler(EulerRot::ZYX);
+            let solid_shape = OrientedBox {
+                center: solid_transform.translation().xy(),
+                angle: solid_angle,
+                half_size: solid.half_size,
+            };
+            let Some(contact) = merge_contacts(&box_contacts(&shape, &solid_shape)) else {
+                continue;
+            };
+
+            let arm = contact.point - shape.center;
+            let impulse = contact_impulse(
+                &contact,
+                arm,
+                linear_velocity.0 + arm.perp() * angular_velocity.0,
+                mass,
+                moment_of_inertia,
+                collider.restitution.max(solid.restitution),
+                (collider.friction * solid.friction).sqrt(),
+            );
+            let linear_change = impulse / mass;
+            let angular_change = arm.perp_dot(impulse) / moment_of_inertia;
+            linear_velocity.0 += linear_change;
+            angular_velocity.0 += angular_change;
+            impact.linear_velocity += linear_change;
+            impact.angular_velocity += angular_change;
+
+            transform.translation += (contact.normal * contact.depth).extend(0.);
+        }
+    }
+}
+
+pub fn switch_material(
+    mut commands: Commands,
+    mut fluid_query: Query<
+        (
+            Entity,
+            &mut FlipFluid,
+            Option<&MpmSolver>,
+            Option<&PbfSolver>,
+        ),
+        With<Tank>,
+    >,
+    keys: Res<ButtonInput<KeyCode>>,
+    demo: Res<Demo>,
+) {
+    let solver = if keys.just_pressed(KeyCode::Digit1) {
+        Solver::Flip {
+            rheology: Some(Rheology::water()),
+            angle_of_repose: None,
+        }
+    } else if keys.just_pressed(KeyCode::Digit2) {
+        Solver::Flip {
+            rheology: Some(Rheology::paint()),
+            angle_of_repose: None,
+        }
+    } else if keys.just_pressed(KeyCode::Digit3) {
+        Solver::Flip {
+            rheology: Some(Rheology::cornstarch()),
+            angle_of_repose: None,
+        }
+    } else if keys.just_pressed(KeyCode::Digit4) {
+        Solver::Flip {
+            rheology: Some(Rheology::ketchup()),
+            angle_of_repose: None,
+        }
+    } else if keys.just_pressed(KeyCode::Digit5) {
+        Solver::Flip {
+            rheology: None,
+            angle_of_repose: Some(SAND_ANGLE_OF_REPOSE.to_radians()),
+        }
+    } else if keys.just_pressed(KeyCode::Digit6) {
+        Solver::Mpm(MpmMaterial::Jelly)
+    } else if keys.just_pressed(KeyCode::Digit7) {
+        Solver::Mpm(MpmMaterial::Snow)
+    } else if keys.just_pressed(KeyCode::Digit8) {
+        Solver::Mpm(MpmMaterial::Clay)
+    } else if keys.just_pressed(KeyCode::Digit9) {
+        Solver::Pbf
+    } else if demo.gas && keys.just_pressed(KeyCode::Digit0) {
+        Solver::Gas(Gas::smoke())
+    } else if demo.gas && keys.just_pressed(KeyCode::Minus) {
+        Solver::Gas(Gas::steam())
+    } else {
+        return;
+    };
+
+    for (entity, mut fluid, mpm, pbf) in &mut fluid_query {
+        // Hand the particles back from the active particle solver
+        if let Some(mpm) = mpm {
+            for i in 0..mpm.num_particles() {
+                fluid.set_particle(mpm.fluid_index(i), mpm.position(i), mpm.velocity(i));
+            }
+            commands.entity(entity).remove::<MpmSolver>();
+        }
+        if let Some(pbf) = pbf {
+            for i in 0..pbf.num_particles() {
+                fluid.set_particle(pbf.fluid_index(i), pbf.position(i), pbf.velocity(i));
+            }
+            commands.entity(entity).remove::<PbfSolver>();
+        }
+
+        match solver {
+            Solver::Flip {
+                rheology,
+                angle_of_repose,
+            } => {
+                fluid.set_gas(None);
+                fluid.set_rheology(rheology);
+                fluid.set_granular(angle_of_repose);
+            }
+            Solver::Gas(gas) => {
+                fluid.set_gas(Some(gas));
+            }
+            Solver::Mpm(material) => {
+                fluid.set_gas(None);
+                commands
+                    .entity(entity)
+                    .insert(MpmSolver::from_fluid(&fluid, material));
+            }
+            Solver::Pbf => {
+                fluid.set_gas(None);
+                commands
+                    .entity(entity)
+                    .insert(PbfSolver::from_fluid(&fluid));
+            }
+        }
+    }
+}
+
+// Cycles through free, hinge, pendulum, spring and rail mounts, placed around the tank
+pub fn switch_mount(
+    mut commands: Commands,
+    mut tank_query: Query<(Entity, &Transform, Option<&Mount>, &mut Grip), With<RigidBody>>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyM) {
+        return;
+    }
+
+    for (entity, transform, mount, mut grip) in &mut tank_query {
+        let center = transform.translation.xy();
+        let (angle, _, _) = transform.rotation.to_euler(EulerRot::ZYX);
+
+        let mount = match mount {
+            None => Some(Mount::Hinge {
+                pivot: center + Vec2::from_angle(angle).rotate(Vec2::new(WIDTH, HEIGHT) * -0.5),
+                min_angle: angle - HINGE_LIMIT,
+                max_angle: angle + HINGE_LIMIT,
+            }),
+            Some(Mount::Hinge { .. }) => Some(Mount::Pendulum {
+                pivot: center + Vec2::Y * PENDULUM_LENGTH,
+                friction: PENDULUM_FRICTION,
+            }),
+            Some(Mount::Pendulum { .. }) => Some(Mount::Spring {
+                anchor: center,
+                stiffness: SPRING_STIFFNESS,
+                damping: SPRING_DAMPING,
+            }),
+            Some(Mount::Spring { .. }) => Some(Mount::Rail {
+                origin: center,
+                direction: Vec2::X,
+                min: RAIL_LENGTH * -0.5,
+                max: RAIL_LENGTH * 0.5,
+            }),
+            Some(Mount::Rail { .. }) => None,
+        };
+
+        match mount {
+            Some(mount) => {
+                commands.entity(entity).insert(mount);
+            }
+            None => {
+                grip.target = center;
+                commands.entity(entity).remove::<Mount>();
+            }
+        }
+    }
+}
+
+pub fn draw_mounts(tank_query: Query<(&Transform, &Mount)>, mut gizmos: Gizmos) {
+    for (transform, mount) in &tank_query {
+        let center = transform.translation.xy();
+        match *mount {
+            Mount::Hinge { pivot, .. } => {
+                gizmos.circle_2d(Isometry2d::from(pivot), 2., YELLOW);
+            }
+            Mount::Pendulum { pivot, .. } => {
+                gizmos.line_2d(pivot, center, YELLOW);
+                gizmos.circle_2d(Isometry2d::from(pivot), 2., YELLOW);
+            }
+            Mount::Spring { anchor, .. } => {
+                gizmos.line_2d(anchor, center, GREEN);
+            }
+            Mount::Rail {
+                origin,
+                direction,
+                min,
+                max,
+            } => {
+                let direction = direction.normalize();
+                gizmos.line_2d(origin + direction * min, origin + direction * max, YELLOW);
+            }
+        }
+    }
+}
+
+pub fn draw_solid_loads(load_query: Query<&SolidLoads>, mut gizmos: Gizmos) {
+    for loads in &load_query {
+        for (_, load) in &loads.0 {
+            let start = load.center_of_pressure;
+            gizmos.arrow_2d(start, start + load.force * LOAD_ARROW_SCALE, RED);
+        }
+    }
+}
+
+pub fn switch_color_mode(mut fluid_query: Query<&mut FlipFluid>, keys: Res<ButtonInput<KeyCode>>) {
+    if !keys.just_pressed(KeyCode::KeyT) {
+        return;
+    }
+
+    for mut fluid in &mut fluid_query {
+        let color_mode = match fluid.color_mode() {
+            ColorMode::Density => ColorMode::Temperature,
+            ColorMode::Temperature => ColorMode::Density,
+        };
+        fluid.set_color_mode(color_mode);
+    }
+}
+
+pub fn switch_advection(mut fluid_query: Query<&mut FlipFluid>, keys: Res<ButtonInput<KeyCode>>) {
+    if !keys.just_pressed(KeyCode::KeyA) {
+        return;
+    }
+
+    for mut fluid in &mut fluid_query {
+        let advection = match fluid.advection() {
+            Advection::Euler => Advection::RungeKutta2,
+            Advection::RungeKutta2 => Advection::RungeKutta3,
+            Advection::RungeKutta3 => Advection::Euler,
+        };
+        fluid.set_advection(advection);
+    }
+}
+
+pub fn squeeze_sponges(mut fluid_query: Query<&mut FlipFluid>, keys: Res<ButtonInput<KeyCode>>) {
+    let squeeze = if keys.pressed(KeyCode::KeyS) { 1. } else { 0. };
+
+    for mut fluid in &mut fluid_query {
+        fluid.set_squeeze(squeeze);
+    }
+}
+
+// Stands the bottle back up on the floor, after it fell over or flew off
+pub fn reset_bottle(
+    mut bottle_query: Query<(
+        &mut BottleFlip,
+        &mut Transform,
+        &mut LinearVelocity,
+        &mut AngularVelocity,
+        &mut MotionEstimator,
+    )>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+
+    for (
+        mut flip,
+        mut transform,
+        mut linear_velocity,
+        mut angular_velocity,
+        mut motion_estimator,
+    ) in &mut bottle_query
+    {
+        transform.translation = Vec3::new(0., FLOOR_Y + HEIGHT * 0.5, transform.translation.z);
+        transform.rotation = Quat::IDENTITY;
+        linear_velocity.0 = Vec2::ZERO;
+        angular_velocity.0 = 0.;
+        // The jump to the floor is no motion the liquid should feel
+        motion_estimator.clear();
+        flip.phase = FlipPhase::Ready;
+    }
+}
+
+// Holding the left mouse button aims, the flick while letting go throws the bottle
+pub fn throw_bottle(
+    mut evr_motion: EventReader<MouseMotion>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    mut bottle_query: Query<(&mut BottleFlip, &mut LinearVelocity, &mut AngularVelocity)>,
+    time: Res<Time>,
+) {
+    let delta = evr_motion.read().map(|ev| ev.delta).sum::<Vec2>() * Vec2::new(1., -1.);
+
+    for (mut flip, mut linear_velocity, mut angular_velocity) in &mut bottle_query {
+        if buttons.just_pressed(MouseButton::Left) {
+            flip.aim();
+        }
+        if time.delta_secs() > 0. {
+            flip.track_flick(delta * POINTER_SCALE / time.delta_secs());
+        }
+        if buttons.just_released(MouseButton::Left) {
+            if let Some((velocity, spin)) = flip.throw() {
+                linear_velocity.0 = velocity;
+                angular_velocity.0 = spin;
+            }
+        }
+    }
+}
+
+// Follows the thrown bottle until it and the water inside have settled
+pub fn judge_bottle_flip(
+    mut bottle_query: Query<(
+        &mut BottleFlip,
+        &FlipFluid,
+        &Transform,
+        &LinearVelocity,
+        &AngularVelocity,
+        &Impact,
+    )>,
+    time: Res<Time>,
+) {
+    for (mut flip, fluid, transform, linear_velocity, angular_velocity, impact) in &mut bottle_query
+    {
+        let (angle, _, _) = transform.rotation.to_euler(EulerRot::ZYX);
+        let water = fluid.mass_properties(Vec2::ZERO);
+        let water_speed = if water.mass > 0. {
+            (2. * water.kinetic_energy / water.mass).sqrt()
+        } else {
+            0.
+        };
+
+        flip.update(FlightSample {
+            dt: time.delta_secs(),
+            angle,
+            speed: linear_velocity.0.length(),
+            angular_velocity: angular_velocity.0,
+            water_speed,
+            touched: !impact.is_empty(),
+        });
+    }
+}
+
+// Aim arrow, the result of the last throw over the bottle and a dot per point scored
+pub fn draw_bottle_flip(bottle_query: Query<(&BottleFlip, &Transform)>, mut gizmos: Gizmos) {
+    for (flip, transform) in &bottle_query {
+        let center = transform.translation.xy();
+        let above = Vec2::new(center.x, center.y + HEIGHT);
+
+        match flip.phase {
+            FlipPhase::Ready | FlipPhase::Flying { .. } => {}
+            FlipPhase::Aiming { flick } => {
+                gizmos.arrow_2d(center, center + flick * 0.2, WHITE);
+            }
+            FlipPhase::Done { upright } => {
+                let color = if upright { GREEN } else { RED };
+                gizmos.circle_2d(Isometry2d::from(above), 6., color);
+            }
+        }
+
+        let first_dot = Vec2::new(-ARENA_HALF_WIDTH + 10., -FLOOR_Y - 10.);
+        for point in 0..flip.score {
+            let position = first_dot + Vec2::X * 8. * point as f32;
+            gizmos.circle_2d(Isometry2d::from(position), 3., GREEN);
+        }
+        for miss in flip.score..flip.attempts {
+            let position = first_dot + Vec2::new(8. * miss as f32, -8.);
+            gizmos.circle_2d(Isometry2d::from(position), 3., RED);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// Adhesion and static contact angle of a solid wall. The contact angle is measured through
+// the liquid, small angles wet the wall and climb it, large angles bead up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wetting {
+    // In radians
+    pub contact_angle: f32,
+    // Acceleration pulling the liquid next to the wall towards it
+    pub adhesion: f32,
+}
+
+impl Wetting {
+    pub fn glass() -> Self {
+        Self {
+            contact_angle: 20_f32.to_radians(),
+            adhesion: 20.,
+        }
+    }
+
+    pub fn wax() -> Self {
+        Self {
+            contact_angle: 110_f32.to_radians(),
+            adhesion: 0.,
+        }
+    }
+
+    pub fn ice() -> Self {
+        Self {
+            contact_angle: 12_f32.to_radians(),
+            adhesion: 10.,
+        }
+    }
+
+    pub fn sand() -> Self {
+        Self {
+            contact_angle: 30_f32.to_radians(),
+            adhesion: 5.,
+        }
+    }
+}
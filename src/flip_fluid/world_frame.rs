@@ -0,0 +1,70 @@
+use bevy::prelude::*;
+
+// Tank moving through a grid that is fixed in world space, instead of a grid carried along
+// with the tank. No fictitious forces are needed, the walls are a moving solid whose velocity
+// is imposed on the grid faces next to them.
+// Local coordinates match the tank-frame mode, the origin is the lower left corner of the tank.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovingTank {
+    pub size: Vec2,
+    pub wall_thickness: f32,
+    // Pose and velocity of the tank center in grid coordinates
+    pub center: Vec2,
+    pub angle: f32,
+    pub linear_velocity: Vec2,
+    pub angular_velocity: f32,
+}
+
+impl MovingTank {
+    pub fn new(size: Vec2, wall_thickness: f32) -> Self {
+        Self {
+            size,
+            wall_thickness,
+            center: Vec2::ZERO,
+            angle: 0.,
+            linear_velocity: Vec2::ZERO,
+            angular_velocity: 0.,
+        }
+    }
+
+    pub fn to_local(self, point: Vec2) -> Vec2 {
+        Vec2::from_angle(-self.angle).rotate(point - self.center) + self.size * 0.5
+    }
+
+    pub fn to_world(self, point: Vec2) -> Vec2 {
+        Vec2::from_angle(self.angle).rotate(point - self.size * 0.5) + self.center
+    }
+
+    pub fn direction_to_world(self, direction: Vec2) -> Vec2 {
+        Vec2::from_angle(self.angle).rotate(direction)
+    }
+
+    // Velocity of the rigid tank at a point in grid coordinates
+    pub fn velocity_at(self, point: Vec2) -> Vec2 {
+        self.linear_velocity + (point - self.center).perp() * self.angular_velocity
+    }
+
+    pub fn is_inside(self, point: Vec2) -> bool {
+        let local = self.to_local(point);
+        local.cmpgt(Vec2::splat(self.wall_thickness)).all()
+            && local.cmplt(self.size - self.wall_thickness).all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_and_world_coordinates_round_trip() {
+        let mut tank = MovingTank::new(Vec2::new(30., 50.), 2.);
+        tank.center = Vec2::new(40., 30.);
+        tank.angle = 0.7;
+
+        let point = Vec2::new(3., 45.);
+        let round_trip = tank.to_local(tank.to_world(point));
+
+        assert!(round_trip.distance(point) < 0.0001);
+        assert!(tank.to_world(Vec2::new(15., 25.)).distance(tank.center) < 0.0001);
+    }
+}
@@ -1,5 +1,7 @@
 use crate::liquid_simulator::grid::Grid;
 use crate::liquid_simulator::spatial_hash::SpatialHash;
+use crate::utils::mechanics::MassProperties;
+use bevy::math::Affine3A;
 use bevy::prelude::*;
 
 #[derive(Component)]
@@ -91,6 +93,58 @@ impl LiquidSimulator {
         self.spacial_hash.set_offset(offset);
     }
 
+    // Mass properties in the space of the entity, angular quantities are about point
+    pub fn mass_properties(&self, density: f32, point: Vec2) -> MassProperties {
+        self.transformed_mass_properties(density, point, Affine3A::IDENTITY, Vec2::ZERO, 0.)
+    }
+
+    // Same in world space, point is in world space too. The entity moves with linear velocity
+    // and turns with angular velocity around its translation, which carries the particles along.
+    pub fn world_mass_properties(
+        &self,
+        density: f32,
+        point: Vec2,
+        transform: &GlobalTransform,
+        linear_velocity: Vec2,
+        angular_velocity: f32,
+    ) -> MassProperties {
+        self.transformed_mass_properties(
+            density,
+            point,
+            transform.affine(),
+            linear_velocity,
+            angular_velocity,
+        )
+    }
+
+    fn transformed_mass_properties(
+        &self,
+        density: f32,
+        point: Vec2,
+        transform: Affine3A,
+        linear_velocity: Vec2,
+        angular_velocity: f32,
+    ) -> MassProperties {
+        let volume = 4. * self.particle_radius * self.particle_radius;
+        let center = transform.translation.xy();
+
+        MassProperties::from_particles(
+            self.particle_positions
+                .iter()
+                .zip(&self.particle_velocities)
+                .map(|(position, velocity)| {
+                    let position = transform
+                        .transform_point3((*position + self.offset).extend(0.))
+                        .xy();
+                    let velocity = transform.transform_vector3(velocity.extend(0.)).xy()
+                        + linear_velocity
+                        + (position - center).perp() * angular_velocity;
+                    (position, velocity, density * volume, volume)
+                }),
+            point,
+        )
+    }
+
     fn set_cell_to_solid(&mut self, i: i32, j: i32) {
         if let Some(mut value) = self.s.get_mut(i, j) {
             *value = 0.; // Solid
@@ -660,4 +714,44 @@ mod tests {
 
         simulator.set_boundary_velocities();
     }
+
+    #[test]
+    fn mass_properties_of_the_particles() {
+        let mut simulator =
+            LiquidSimulator::new(vec![Vec2::new(1., 2.), Vec2::new(3., 2.)], 0.5, 5, 5, 10.)
+                .with_offset(Vec2::new(-5., 0.));
+        simulator.particle_velocities = vec![Vec2::new(0., 1.), Vec2::new(0., -1.)];
+
+        let properties = simulator.mass_properties(2., Vec2::new(-3., 2.));
+
+        assert_eq!(properties.mass, 4.);
+        assert_eq!(properties.volume, 2.);
+        assert_eq!(properties.center_of_mass, Vec2::new(-3., 2.));
+        assert_eq!(properties.linear_momentum, Vec2::ZERO);
+        assert_eq!(properties.angular_momentum, -4.);
+        assert_eq!(properties.moment_of_inertia, 4.);
+        assert_eq!(properties.kinetic_energy, 2.);
+    }
+
+    #[test]
+    fn world_mass_properties_move_with_the_entity() {
+        let simulator =
+            LiquidSimulator::new(vec![Vec2::new(1., 0.), Vec2::new(-1., 0.)], 0.5, 5, 5, 10.);
+        let transform = GlobalTransform::from(
+            Transform::from_xyz(10., 5., 0.).with_rotation(Quat::from_rotation_z(0.5)),
+        );
+
+        let properties = simulator.world_mass_properties(
+            2.,
+            Vec2::new(10., 5.),
+            &transform,
+            Vec2::new(3., 0.),
+            2.,
+        );
+
+        // Carried along at 3 and spun around the translation like a rigid body
+        assert!(properties.linear_momentum.distance(Vec2::new(12., 0.)) < 0.0001);
+        assert!((properties.angular_momentum - 8.).abs() < 0.0001);
+        assert!((properties.kinetic_energy - 26.).abs() < 0.0001);
+    }
 }
@@ -1,7 +1,7 @@
 mod components;
 mod demo_systems;
 mod grid;
-mod spatial_hash;
+pub mod spatial_hash;
 mod systems;
 
 use crate::liquid_simulator::demo_systems::{position_liquid_particles, spawn_tank};
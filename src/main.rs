@@ -9,6 +9,7 @@ mod liquid_simulator;
 mod pic_flip;
 mod utils;
 
+use crate::flip_fluid::demo::Demo;
 use crate::flip_fluid::FlipFluidPlugin;
 use crate::liquid_simulator::{LiquidSimulationDebugPlugin, LiquidSimulatorPlugin};
 use crate::pic_flip::PicFlipPlugin;
@@ -29,6 +30,22 @@ fn main() {
         // .add_plugins(PicFlipPlugin)
         // .add_plugins(LiquidSimulatorPlugin)
         // .add_plugins(LiquidSimulationDebugPlugin)
+        // Switch on the features the tank is spawned with
+        .insert_resource(Demo {
+            diffuse_particles: false,
+            ballistic_droplets: false,
+            vorticity_confinement: false,
+            heat_transfer: false,
+            ice: false,
+            gas: false,
+            air: false,
+            sponge: false,
+            sediment: false,
+            wetting: false,
+            world_frame: false,
+            obstacle: false,
+            bottle_flip: false,
+        })
         .add_plugins(FlipFluidPlugin)
         .run();
 }
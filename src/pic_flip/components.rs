@@ -74,6 +74,8 @@ impl FluidSimulator {
         self.0.set_boundary_velocities();
 
         self.0.project_pressure(100, 1.9, 1., 2.7);
+
+        self.0.extrapolate_velocities(2);
     }
 
     pub fn grid_to_particle(&self, point: Vec2) -> Option<Vec2> {
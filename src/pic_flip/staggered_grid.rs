@@ -188,6 +188,87 @@ impl StaggeredGrid {
         }
     }
 
+    pub fn extrapolate_velocities(&mut self, layers: usize) {
+        Self::extrapolate_velocity_components(
+            &mut self.horizontal_velocities,
+            &self.cell_types,
+            (1, 0),
+            layers,
+        );
+
+        Self::extrapolate_velocity_components(
+            &mut self.vertical_velocities,
+            &self.cell_types,
+            (0, 1),
+            layers,
+        );
+    }
+
+    // Faces next to fluid cells are known, the surrounding faces are filled layer by layer
+    // with the average of their known neighbours. Faces touching solid cells are left as is.
+    fn extrapolate_velocity_components(
+        velocities: &mut Grid<f32>,
+        cell_types: &Grid<CellType>,
+        (di, dj): (i32, i32),
+        layers: usize,
+    ) {
+        let cols = velocities.cols() as i32;
+        let rows = velocities.rows() as i32;
+
+        let mut known: Grid<bool> = Grid::new(cols as usize, rows as usize);
+
+        for i in 0..cols {
+            for j in 0..rows {
+                let first = cell_types.get_at(i - di, j - dj);
+                let second = cell_types.get_at(i, j);
+                let is_fluid = first == Some(&CellType::FLUID) || second == Some(&CellType::FLUID);
+                let is_solid = first == Some(&CellType::SOLID) || second == Some(&CellType::SOLID);
+
+                if let Some(value) = known.get_at_mut(i, j) {
+                    *value = is_fluid && !is_solid;
+                }
+            }
+        }
+
+        for _ in 0..layers {
+            let mut layer = vec![];
+
+            for i in 0..cols {
+                for j in 0..rows {
+                    if known.get_at(i, j) == Some(&true)
+                        || cell_types.get_at(i - di, j - dj) == Some(&CellType::SOLID)
+                        || cell_types.get_at(i, j) == Some(&CellType::SOLID)
+                    {
+                        continue;
+                    }
+
+                    let mut sum = 0.;
+                    let mut count = 0.;
+
+                    for (ni, nj) in [(i - 1, j), (i + 1, j), (i, j - 1), (i, j + 1)] {
+                        if known.get_at(ni, nj) == Some(&true) {
+                            sum += velocities.get_at(ni, nj).unwrap_or(&0.);
+                            count += 1.;
+                        }
+                    }
+
+                    if count > 0. {
+                        layer.push((i, j, sum / count));
+                    }
+                }
+            }
+
+            for (i, j, velocity) in layer {
+                if let Some(target) = velocities.get_at_mut(i, j) {
+                    *target = velocity;
+                }
+                if let Some(value) = known.get_at_mut(i, j) {
+                    *value = true;
+                }
+            }
+        }
+    }
+
     fn contribute_to_solid_cell_count(&self, i: i32, j: i32) -> f32 {
         match self.cell_types.get_at(i, j) {
             None => 0.,
@@ -546,4 +627,23 @@ mod tests {
         //     0.1875 * -20.
         // );
     }
+
+    #[test]
+    fn extrapolate_velocities_into_empty_cells() {
+        let mut grid = StaggeredGrid::new(5, 5, 10., Vec2::ZERO);
+        grid.set_particle_cell_to_fluid(vec2(15., 15.));
+
+        *grid.horizontal_velocities.get_at_mut(1, 1).unwrap() = 4.;
+        *grid.horizontal_velocities.get_at_mut(2, 1).unwrap() = 4.;
+
+        grid.extrapolate_velocities(1);
+
+        assert_eq!(*grid.horizontal_velocities.get_at(1, 2).unwrap(), 4.);
+        assert_eq!(*grid.horizontal_velocities.get_at(3, 1).unwrap(), 4.);
+        assert_eq!(*grid.horizontal_velocities.get_at(1, 3).unwrap(), 0.);
+        assert_eq!(
+            grid.interpolate_velocity(vec2(15., 25.)),
+            Some(vec2(4., 0.))
+        );
+    }
 }
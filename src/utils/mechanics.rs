@@ -1,5 +1,5 @@
 use bevy::prelude::*;
-use std::ops::Neg;
+use std::collections::VecDeque;
 
 // https://en.wikipedia.org/wiki/Instant_centre_of_rotation#Pole_of_a_planar_displacement
 pub fn pole_of_planar_displacement(
@@ -24,28 +24,184 @@ pub fn pole_of_planar_displacement(
     Vec2::new(px, py)
 }
 
-pub fn center_of_rotation(
-    point_a: Vec2,
-    velocity_a: Vec2,
-    point_b: Vec2,
-    velocity_b: Vec2,
+// Below this the motion counts as a pure translation, the center of rotation would lie
+// further away than anything in the scene
+const MIN_ANGULAR_VELOCITY: f32 = 1e-4;
+
+// Instantaneous center of rotation from the velocity of any point of the body and the angular
+// velocity, None for a pure translation.
+pub fn center_of_rotation(point: Vec2, velocity: Vec2, angular_velocity: f32) -> Option<Vec2> {
+    if angular_velocity.abs() < MIN_ANGULAR_VELOCITY {
+        return None;
+    }
+
+    Some(point + velocity.perp() / angular_velocity)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RigidMotion {
+    // Of the origin of the moving frame
+    pub linear_velocity: Vec2,
+    pub linear_acceleration: Vec2,
+    pub angular_velocity: f32,
+    pub angular_acceleration: f32,
+    // None while translating without turning
+    pub center_of_rotation: Option<Vec2>,
+}
+
+// Estimates the motion of a rigid frame from the last few GlobalTransforms. A quadratic is
+// fitted to the positions and angles by least squares, which filters the noise of single
+// frame differences and is exact for constant accelerations.
+#[derive(Component, Debug, Clone)]
+pub struct MotionEstimator {
+    capacity: usize,
+    // Time, position and unwrapped angle
+    samples: VecDeque<(f32, Vec2, f32)>,
+}
+
+impl MotionEstimator {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(2),
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, time: f32, transform: &GlobalTransform) {
+        let position = transform.translation().xy();
+        let mut angle = transform.affine().matrix3.x_axis.xy().to_angle();
+
+        if let Some(&(last_time, _, last_angle)) = self.samples.back() {
+            if time <= last_time {
+                return;
+            }
+            // Continue from the last angle instead of jumping at +-PI
+            let turn = Vec2::from_angle(last_angle).angle_to(Vec2::from_angle(angle));
+            angle = last_angle + turn;
+        }
+
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((time, position, angle));
+    }
+
+    // Forgets the history, after a jump in velocity the motion no longer fits a quadratic
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn estimate(&self) -> RigidMotion {
+        let (Some(first), Some(last)) = (self.samples.front(), self.samples.back()) else {
+            return RigidMotion::default();
+        };
+        let duration = last.0 - first.0;
+        if duration <= 0. {
+            return RigidMotion::default();
+        }
+
+        // Sample times scaled to [-1, 0], the estimate is for the latest sample
+        let times = self
+            .samples
+            .iter()
+            .map(|sample| (sample.0 - last.0) / duration);
+        let x = fit_derivatives(times.clone().zip(self.samples.iter().map(|s| s.1.x)));
+        let y = fit_derivatives(times.clone().zip(self.samples.iter().map(|s| s.1.y)));
+        let angle = fit_derivatives(times.zip(self.samples.iter().map(|s| s.2)));
+
+        let linear_velocity = Vec2::new(x.0, y.0) / duration;
+        let angular_velocity = angle.0 / duration;
+
+        RigidMotion {
+            linear_velocity,
+            linear_acceleration: Vec2::new(x.1, y.1) / (duration * duration),
+            angular_velocity,
+            angular_acceleration: angle.1 / (duration * duration),
+            center_of_rotation: center_of_rotation(last.1, linear_velocity, angular_velocity),
+        }
+    }
+}
+
+// First and second derivative at 0 of the least squares quadratic through the samples,
+// falls back to a line for too few samples
+fn fit_derivatives(samples: impl Iterator<Item = (f32, f32)> + Clone) -> (f32, f32) {
+    let mut normal = Mat3::ZERO;
+    let mut rhs = Vec3::ZERO;
+    for (t, value) in samples {
+        let basis = Vec3::new(1., t, t * t);
+        normal += Mat3::from_cols(basis * basis.x, basis * basis.y, basis * basis.z);
+        rhs += basis * value;
+    }
+
+    if normal.determinant().abs() > 1e-6 {
+        let coefficients = normal.inverse() * rhs;
+        return (coefficients.y, 2. * coefficients.z);
+    }
+
+    // Two samples, at -1 and 0
+    let normal = Mat2::from_cols(normal.x_axis.xy(), normal.y_axis.xy());
+    if normal.determinant().abs() > 1e-6 {
+        let coefficients = normal.inverse() * rhs.xy();
+        return (coefficients.y, 0.);
+    }
+
+    (0., 0.)
+}
+
+// Acceleration of a point in an accelerating and rotating frame, i.e. the linear acceleration
+// plus the Euler and centrifugal accelerations about the center of rotation.
+pub fn frame_acceleration(
+    point: Vec2,
+    linear_acceleration: Vec2,
+    angular_acceleration: f32,
+    angular_velocity: f32,
+    rotation_center: Vec2,
 ) -> Vec2 {
-    let slope_a = (velocity_a.x / velocity_a.y).neg();
-    let slope_b = (velocity_b.x / velocity_b.y).neg();
+    let to_center = rotation_center - point;
+
+    linear_acceleration + to_center.perp() * angular_acceleration
+        - to_center * angular_velocity * angular_velocity
+}
+
+// Totals over a set of particles, angular quantities are about a reference point
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MassProperties {
+    pub mass: f32,
+    pub volume: f32,
+    pub center_of_mass: Vec2,
+    pub linear_momentum: Vec2,
+    pub angular_momentum: f32,
+    pub moment_of_inertia: f32,
+    pub kinetic_energy: f32,
+}
 
-    let midpoint_a = point_a + velocity_a * 0.5;
-    let midpoint_b = point_b + velocity_b * 0.5;
+impl MassProperties {
+    // Particles as position, velocity, mass and volume
+    pub fn from_particles(
+        particles: impl Iterator<Item = (Vec2, Vec2, f32, f32)>,
+        point: Vec2,
+    ) -> Self {
+        let mut properties = Self::default();
+        let mut first_moment = Vec2::ZERO;
 
-    let px = (slope_a * midpoint_a.x - midpoint_a.y - slope_b * midpoint_b.x + midpoint_b.y)
-        / (slope_a - slope_b);
-    let py = slope_b * (px - midpoint_b.x) + midpoint_b.y;
+        for (position, velocity, mass, volume) in particles {
+            let momentum = velocity * mass;
+            let arm = position - point;
 
-    let center = Vec2::new(px, py);
+            properties.mass += mass;
+            properties.volume += volume;
+            first_moment += position * mass;
+            properties.linear_momentum += momentum;
+            properties.angular_momentum += arm.perp_dot(momentum);
+            properties.moment_of_inertia += arm.length_squared() * mass;
+            properties.kinetic_energy += 0.5 * velocity.dot(momentum);
+        }
 
-    if center.is_finite() {
-        center
-    } else {
-        Vec2::ZERO
+        if properties.mass > 0. {
+            properties.center_of_mass = first_moment / properties.mass;
+        }
+
+        properties
     }
 }
 
@@ -68,14 +224,146 @@ mod tests {
 
     #[test]
     fn center_of_rotation_works() {
-        let point_a = Vec2::new(2., 9.);
-        let velocity_a = Vec2::new(-1., -5.);
-        let point_b = Vec2::new(6., 1.);
-        let velocity_b = Vec2::new(3., 7.);
+        let point = Vec2::new(2., 9.);
+        let velocity = Vec2::new(-3., -2.);
 
-        let center = center_of_rotation(point_a, velocity_a, point_b, velocity_b);
+        let center = center_of_rotation(point, velocity, 1.).unwrap();
 
         assert!((center.x - 4.).abs() < 0.0001);
         assert!((center.y - 6.).abs() < 0.0001);
+        assert_eq!(center_of_rotation(point, velocity, 0.), None);
+    }
+
+    fn estimate(trajectory: impl Fn(f32) -> (Vec2, f32), frames: usize) -> RigidMotion {
+        let mut estimator = MotionEstimator::new(8);
+        for frame in 0..frames {
+            let time = frame as f32 / 60.;
+            let (position, angle) = trajectory(time);
+            let transform = Transform::from_translation(position.extend(0.))
+                .with_rotation(Quat::from_rotation_z(angle));
+            estimator.push(time, &GlobalTransform::from(transform));
+        }
+        estimator.estimate()
+    }
+
+    #[test]
+    fn motion_estimator_handles_pure_translation() {
+        let velocity = Vec2::new(3., -1.);
+        let acceleration = Vec2::new(0., -4.);
+        let motion = estimate(
+            |t| {
+                (
+                    Vec2::new(1., 2.) + velocity * t + acceleration * t * t * 0.5,
+                    0.5,
+                )
+            },
+            20,
+        );
+
+        let time = 19. / 60.;
+        assert!(
+            motion
+                .linear_velocity
+                .distance(velocity + acceleration * time)
+                < 0.01
+        );
+        assert!(motion.linear_acceleration.distance(acceleration) < 0.1);
+        assert!(motion.angular_velocity.abs() < 0.0001);
+        assert_eq!(motion.center_of_rotation, None);
+    }
+
+    #[test]
+    fn motion_estimator_finds_center_of_rotation() {
+        // Turning through PI, where the angle wraps around
+        let center = Vec2::new(4., 6.);
+        let angular_velocity = 2.;
+        let motion = estimate(
+            |t| {
+                let angle = 3. + angular_velocity * t;
+                (center + Vec2::from_angle(angle) * 3., angle)
+            },
+            20,
+        );
+
+        assert!((motion.angular_velocity - angular_velocity).abs() < 0.01);
+        assert!(motion.angular_acceleration.abs() < 0.1);
+        assert!(motion.center_of_rotation.unwrap().distance(center) < 0.05);
+        assert!((motion.linear_acceleration.length() - 12.).abs() < 0.1);
+    }
+
+    #[test]
+    fn motion_estimator_filters_noise() {
+        let velocity = Vec2::new(5., 0.);
+        let jitter = |t: f32| {
+            Vec2::Y
+                * 0.01
+                * if (t * 60.).round() % 2. == 0. {
+                    1.
+                } else {
+                    -1.
+                }
+        };
+        let motion = estimate(|t| (velocity * t + jitter(t), 0.), 20);
+
+        // A single frame difference would be off by 1.2
+        assert!(motion.linear_velocity.distance(velocity) < 0.2);
+    }
+
+    #[test]
+    fn motion_estimator_follows_a_moving_parent() {
+        // A glass at rest on a platform that accelerates and turns
+        let offset = Vec3::new(5., 1., 0.);
+        let acceleration = Vec2::new(2., 0.);
+        let angular_velocity = 1.;
+        let mut estimator = MotionEstimator::new(8);
+        for frame in 0..20 {
+            let time = frame as f32 / 60.;
+            let platform = GlobalTransform::from(
+                Transform::from_translation((acceleration * time * time * 0.5).extend(0.))
+                    .with_rotation(Quat::from_rotation_z(angular_velocity * time)),
+            );
+            estimator.push(
+                time,
+                &platform.mul_transform(Transform::from_translation(offset)),
+            );
+        }
+        let motion = estimator.estimate();
+
+        let angle = angular_velocity * 19. / 60.;
+        let centripetal = -Vec2::from_angle(angle).rotate(offset.xy()) * angular_velocity.powi(2);
+        // The fit trails the changing acceleration by about half its window
+        assert!((motion.angular_velocity - angular_velocity).abs() < 0.01);
+        assert!(
+            motion
+                .linear_acceleration
+                .distance(acceleration + centripetal)
+                < 0.5
+        );
+    }
+
+    #[test]
+    fn frame_acceleration_works() {
+        let acceleration = frame_acceleration(Vec2::new(3., 0.), Vec2::NEG_Y, 2., 1., Vec2::ZERO);
+
+        assert!((acceleration.x - 3.).abs() < 0.0001);
+        assert!((acceleration.y + 7.).abs() < 0.0001);
+    }
+
+    #[test]
+    fn mass_properties_of_particles() {
+        let particles = [
+            (Vec2::new(1., 0.), Vec2::new(0., 2.), 1., 0.5),
+            (Vec2::new(-1., 0.), Vec2::new(0., -2.), 3., 1.5),
+        ];
+
+        let properties = MassProperties::from_particles(particles.into_iter(), Vec2::ZERO);
+
+        assert_eq!(properties.mass, 4.);
+        assert_eq!(properties.volume, 2.);
+        assert_eq!(properties.center_of_mass, Vec2::new(-0.5, 0.));
+        assert_eq!(properties.linear_momentum, Vec2::new(0., -4.));
+        assert_eq!(properties.angular_momentum, 8.);
+        assert_eq!(properties.moment_of_inertia, 4.);
+        assert_eq!(properties.kinetic_energy, 8.);
     }
 }